@@ -32,6 +32,24 @@ pub enum Command {
     Run {
         #[arg(short, long, help = "Root path")]
         root: Option<String>,
+        #[arg(long, help = "Include pages marked as drafts")]
+        drafts: bool,
+        #[arg(long, help = "Include pages dated in the future")]
+        future: bool,
+        #[arg(
+            short,
+            long,
+            default_value_t = 0,
+            help = "Number of pages rendered at once and of worker threads, 0 for one per CPU"
+        )]
+        jobs: usize,
+        #[arg(
+            long,
+            help = "Record how long each query takes, writing trace.json at the root of the site"
+        )]
+        timings: bool,
+        #[arg(long, help = "Build for previewing: keep the HTML readable")]
+        dev: bool,
     },
 }
 
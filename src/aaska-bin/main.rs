@@ -3,11 +3,13 @@ mod prelude {
     pub use eyre::{Context, ContextCompat, WrapErr, eyre};
     pub use tracing::{debug, error, info, trace, warn};
 }
-use std::path::Path;
+use std::sync::Arc;
+
+use aaska2::vfs::{DiskVfs, Vfs};
 
 use prelude::*;
 
-use eyre::{Context, Result, bail};
+use eyre::{Result, bail};
 
 mod cli;
 
@@ -30,14 +32,19 @@ macro_rules! check {
     };
 }
 
-#[tokio::main]
-async fn main() {
+fn main() {
     let cli = cli::parse_args();
     init_tracing(&cli);
-    aaska2::init();
 
     match cli.command() {
-        cli::Command::Run { root } => {
+        cli::Command::Run {
+            root,
+            drafts,
+            future,
+            jobs,
+            timings,
+            dev,
+        } => {
             let path = root.clone().unwrap_or_else(|| {
                 std::env::current_dir()
                     .expect_tracing("Failed to get current directory")
@@ -45,23 +52,82 @@ async fn main() {
                     .expect_tracing("Failed to convert current directory to string")
                     .to_string()
             });
-            run(&path).expect_tracing("Failed to run Aaska")
+            let options = aaska2::build::BuildOptions {
+                drafts: *drafts,
+                future: *future,
+                jobs: *jobs,
+                profile: if *dev {
+                    aaska2::build::Profile::Dev
+                } else {
+                    aaska2::build::Profile::Production
+                },
+            };
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(options.jobs())
+                .build_global()
+                .expect_tracing("Failed to start the thread pool");
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(options.jobs())
+                .enable_all()
+                .build()
+                .expect_tracing("Failed to start the async runtime")
+                .block_on(run(&path, options, *timings))
+                .expect_tracing("Failed to run Aaska")
         }
     }
 }
 
-fn run(path: &str) -> Result<()> {
+async fn run(path: &str, options: aaska2::build::BuildOptions, timings: bool) -> Result<()> {
     info!("Run");
     let base_paths = compute_aaska_paths(path);
     if !base_paths.are_valid() {
         bail!("Invalid base paths");
     }
+    let vfs: Arc<dyn Vfs> = Arc::new(DiskVfs);
+    let db = Arc::new(aaska2::db::AaskaDb::open(&base_paths.root, vfs.clone())?);
+    if timings {
+        aaska2::timings::enable(&db);
+    }
+    let output = aaska2::build::build(&db, options).await?;
+    if timings {
+        let events = aaska2::timings::take(&db);
+        let trace = base_paths.root.join("trace.json");
+        std::fs::write(&trace, aaska2::timings::chrome_trace(&events))
+            .wrap_err_with(|| format!("Failed to write {}", trace.display()))?;
+        eprintln!("{}", aaska2::timings::summary(&events, 20));
+    }
 
-    let _db = aaska2::db::AaskaDb::new_simple();
-    let md_files = glob(base_paths.content, "**/*.md")?;
-
-    dbg!(md_files);
+    let written = aaska2::build::write(&output, &base_paths.public)?;
+    println!(
+        "{} pages rendered, {} reused from cache, {} written, {} deleted",
+        output.pages_rendered, output.pages_reused, written.written, written.deleted
+    );
 
+    // A page that cannot be read, e.g. because it links outside of the site, is left out
+    for error in &output.load_errors {
+        eprintln!("error: {}\n", error);
+    }
+    let mut warnings = 0;
+    for diagnostic in &output.diagnostics {
+        if !diagnostic.is_error() {
+            warnings += 1;
+        }
+        let source = vfs
+            .read(&diagnostic.file)
+            .map(|bytes| match aaska2::encoding::decode(&bytes) {
+                Ok(source) => source.into_owned(),
+                Err(_) => String::from_utf8_lossy(&bytes).into_owned(),
+            })
+            .ok();
+        eprintln!("{}", diagnostic.render(source.as_deref()));
+    }
+    if warnings > 0 {
+        eprintln!("warning: {} warning(s) emitted\n", warnings);
+    }
+    let errors = output.error_count();
+    if errors > 0 {
+        bail!("Build failed with {} error(s)", errors);
+    }
     Ok(())
 }
 
@@ -112,13 +178,19 @@ fn init_tracing(cli: &cli::Args) {
 struct AaskaBasePaths {
     root: std::path::PathBuf,
     content: std::path::PathBuf,
+    public: std::path::PathBuf,
 }
 
 fn compute_aaska_paths(root: &str) -> AaskaBasePaths {
     let root = aaska2::path::soft_cannonicalize_cwd(root);
     let content = aaska2::path::soft_cannonicalize_rel("content", &root);
+    let public = aaska2::path::soft_cannonicalize_rel("public", &root);
 
-    AaskaBasePaths { root, content }
+    AaskaBasePaths {
+        root,
+        content,
+        public,
+    }
 }
 
 impl AaskaBasePaths {
@@ -140,28 +212,3 @@ impl AaskaBasePaths {
         &self.content
     }
 }
-
-fn glob(base: impl AsRef<Path>, glob: &str) -> Result<Vec<std::path::PathBuf>> {
-    // check base is valid path string
-    let pattern = base
-        .as_ref()
-        .join(glob)
-        .to_str()
-        .wrap_err_with(|| {
-            format!(
-                "Failed to construct glob pattern from base {} and glob {}",
-                base.as_ref().display(),
-                glob
-            )
-        })?
-        .to_string();
-
-    let paths = glob::glob(&pattern)
-        .wrap_err_with(|| format!("Failed to read glob pattern {}", pattern))?
-        .map(|res| {
-            res.wrap_err_with(|| format!("Failed to read path from glob pattern {}", pattern))
-        })
-        .collect::<Result<Vec<_>>>()?;
-
-    Ok(paths)
-}
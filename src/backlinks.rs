@@ -0,0 +1,158 @@
+//! Backlinks: for every page, the pages linking to it and the text around each link.
+//!
+//! The links of a page are a query of their own, so editing a page without touching its links
+//! stops there. When links do change, the site-wide index is rebuilt, but only the targets whose
+//! [`backlinks`] actually changed are re-rendered.
+
+use crate::db::{Db, SourceFile, render_chonk};
+use crate::diagnostics::Span;
+use crate::path::SrcPath;
+use crate::site::{page_meta, published_pages};
+use picante::PicanteResult;
+
+/// Characters of context kept on each side of a link in its snippet.
+const SNIPPET_RADIUS: usize = 80;
+
+/// A link from a page to another page of the site.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct PageLink {
+    pub target: SrcPath,
+    /// Text of the paragraph (or list item, heading, ...) around the link
+    pub snippet: String,
+    /// The link in the source page
+    pub span: Span,
+}
+
+/// A page linking to the page being rendered.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Backlink {
+    pub src: SrcPath,
+    pub title: String,
+    pub url: String,
+    pub snippet: String,
+}
+
+/// Pages linked from `md_file`, one entry per target.
+#[picante::tracked]
+pub async fn page_links<DB: Db>(db: &DB, md_file: SourceFile) -> PicanteResult<Vec<PageLink>> {
+    let _timing = crate::timings::query(db, "page_links", md_file);
+    let mut links = render_chonk(db, md_file).await?.page_links;
+    let mut seen = Vec::new();
+    links.retain(|link| {
+        let first = !seen.contains(&link.target);
+        seen.push(link.target.clone());
+        first
+    });
+    Ok(links)
+}
+
+/// Every link between published pages, keyed by target.
+#[picante::tracked]
+pub async fn backlink_index<DB: Db>(db: &DB) -> PicanteResult<Vec<(SrcPath, Backlink)>> {
+    let _timing = crate::timings::query(db, "backlink_index", ());
+    let mut index = Vec::new();
+    for page in published_pages(db).await? {
+        let meta = page_meta(db, page.file).await?;
+        for link in page_links(db, page.file).await? {
+            if link.target == meta.src {
+                continue;
+            }
+            index.push((
+                link.target,
+                Backlink {
+                    src: meta.src.clone(),
+                    title: meta.title().to_string(),
+                    url: meta.url.clone(),
+                    snippet: link.snippet,
+                },
+            ));
+        }
+    }
+    index.sort_by(|a, b| (a.0.rel_path(), &a.1.url).cmp(&(b.0.rel_path(), &b.1.url)));
+    Ok(index)
+}
+
+/// Pages linking to `target`, sorted by URL.
+#[picante::tracked]
+pub async fn backlinks<DB: Db>(db: &DB, target: SrcPath) -> PicanteResult<Vec<Backlink>> {
+    let _timing = crate::timings::query(db, "backlinks", target.clone());
+    Ok(backlink_index(db)
+        .await?
+        .into_iter()
+        .filter(|(t, _)| *t == target)
+        .map(|(_, backlink)| backlink)
+        .collect())
+}
+
+/// Cuts `text` down to [`SNIPPET_RADIUS`] characters on each side of byte offset `at`, marking
+/// the cuts with an ellipsis. Whitespace is collapsed. An offset past the end or inside a
+/// character is moved back to the closest character boundary.
+pub fn snippet(text: &str, at: usize) -> String {
+    let mut at = at.min(text.len());
+    while !text.is_char_boundary(at) {
+        at -= 1;
+    }
+    let before: Vec<&str> = text[..at].split_whitespace().collect();
+    let after: Vec<&str> = text[at..].split_whitespace().collect();
+    let before = before.join(" ");
+    let after = after.join(" ");
+    let space = if text[..at].ends_with(char::is_whitespace) && !before.is_empty() {
+        " "
+    } else {
+        ""
+    };
+
+    let mut out = String::new();
+    let skip = before.chars().count().saturating_sub(SNIPPET_RADIUS);
+    if skip > 0 {
+        out.push('…');
+        out.extend(before.chars().skip(skip));
+    } else {
+        out.push_str(&before);
+    }
+    out.push_str(space);
+    out.extend(after.chars().take(SNIPPET_RADIUS));
+    if after.chars().count() > SNIPPET_RADIUS {
+        out.push('…');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snippet() {
+        assert_eq!(snippet("See  the\nother page.", 5), "See the other page.");
+        assert_eq!(snippet("link", 0), "link");
+
+        let long = format!("{} link {}", "a".repeat(100), "b".repeat(100));
+        let s = snippet(&long, 101);
+        assert!(s.starts_with('…') && s.ends_with('…'));
+        assert!(s.contains(" link "));
+        assert_eq!(s.chars().count(), 2 * SNIPPET_RADIUS + 3);
+
+        assert_eq!(snippet("a", 13), "a");
+        assert_eq!(snippet("café link", 4), "café link");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_nested_list_snippet() {
+        use crate::build::{BuildOptions, Profile, build};
+        use crate::db::AaskaDb;
+        use crate::vfs::MemoryVfs;
+
+        let vfs = std::sync::Arc::new(MemoryVfs::new());
+        vfs.insert("/site/content/a.md", "- see the long [x](x.md)\n  - a\n");
+        vfs.insert("/site/content/x.md", "# X\n");
+        let db = std::sync::Arc::new(AaskaDb::open("/site", vfs).unwrap());
+        let options = BuildOptions {
+            profile: Profile::Dev,
+            ..Default::default()
+        };
+        let output = build(&db, options).await.unwrap();
+        let page = String::from_utf8_lossy(&output.files["x/index.html"]);
+        assert!(page.contains("<p>see the long x</p>"), "{}", page);
+    }
+}
@@ -0,0 +1,532 @@
+//! Building a whole site in memory, for programs embedding aaska. Nothing is written to disk: the
+//! caller gets every output file and the problems found, and decides what to do with them.
+//!
+//! The database is owned by the caller, so building again with the same one only recomputes what
+//! changed since the previous build. [`write`] then only touches the files that changed, so their
+//! modification times stay meaningful to tools like rsync.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use eyre::{Context, Result};
+use picante::PicanteResult;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::db::{AaskaDb, SourceFile};
+use crate::diagnostics::Diagnostic;
+use crate::manifest::Manifest;
+use crate::minify::MinifyOptions;
+use crate::path::SrcPath;
+use crate::shortcode::ShortcodeTemplates;
+use crate::site::{ContentSet, PublishOptions};
+use crate::timings::lookup;
+use crate::{OutputContents, OutputFile};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct BuildOptions {
+    /// Include pages marked `draft: true`
+    pub drafts: bool,
+    /// Include pages dated in the future
+    pub future: bool,
+    /// Number of pages rendered at once, 0 for one per CPU
+    pub jobs: usize,
+    pub profile: Profile,
+}
+
+/// What the build is for, which decides the defaults of output stages such as minification.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Profile {
+    /// Previewing the site while writing it: output is kept readable
+    Dev,
+    /// Publishing the site: HTML is minified
+    #[default]
+    Production,
+}
+
+impl BuildOptions {
+    /// Number of pages rendered at once, at least 1.
+    pub fn jobs(&self) -> usize {
+        match self.jobs {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            jobs => jobs,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildOutput {
+    /// Contents of every output file, by path relative to the output directory, including
+    /// [`crate::manifest::MANIFEST_PATH`]
+    pub files: BTreeMap<String, Vec<u8>>,
+    /// Problems found in the pages, see [`crate::diagnostics::page_diagnostics`]
+    pub diagnostics: Vec<Diagnostic>,
+    /// Pages that could not be loaded, e.g. because they link outside of the site. They are left
+    /// out of the build.
+    pub load_errors: Vec<String>,
+    /// Pages rendered by this build
+    pub pages_rendered: usize,
+    /// Pages whose output was reused from a previous build with the same database
+    pub pages_reused: usize,
+}
+
+impl BuildOutput {
+    pub fn error_count(&self) -> usize {
+        self.load_errors.len() + self.diagnostics.iter().filter(|d| d.is_error()).count()
+    }
+}
+
+/// Builds the site of `db`, reading the pages under its content directory and every other source
+/// file it loaded before again. Pages are rendered by tasks of their own, so this must run inside
+/// a tokio runtime.
+pub async fn build(db: &Arc<AaskaDb>, options: BuildOptions) -> Result<BuildOutput> {
+    let config = crate::config(db);
+    db.refresh_sources()?;
+    let content = config.site.content_dir();
+    let md_files = config
+        .vfs
+        .glob(&content, "**/*.md")
+        .wrap_err_with(|| format!("Failed to list pages in {}", content.display()))?;
+
+    let mut output = BuildOutput::default();
+    let mut pages = Vec::new();
+    for path in &md_files {
+        match db.load_page(&content, path) {
+            Ok(page) => pages.push(page),
+            Err(e) => output.load_errors.push(format!("{:#}", e)),
+        }
+    }
+    ContentSet::set(db, pages.clone())?;
+
+    let shortcodes_dir =
+        crate::path::soft_cannonicalize_cwd(config.site.templates_dir().join("shortcodes"));
+    let mut templates = Vec::new();
+    for path in config
+        .vfs
+        .glob(&shortcodes_dir, "*.html")
+        .wrap_err_with(|| format!("Failed to list templates in {}", shortcodes_dir.display()))?
+    {
+        let template = SrcPath::try_from_relaxed_path(&path, "")
+            .map_err(eyre::Report::from)
+            .and_then(|src| db.input(src));
+        match template {
+            Ok(template) => {
+                let name = template.path(db)?.filename_no_ext().to_string();
+                templates.push((name, template));
+            }
+            Err(e) => output.load_errors.push(format!("{:#}", e)),
+        }
+    }
+    ShortcodeTemplates::set(db, templates)?;
+    PublishOptions::set(db, options.drafts, options.future, crate::site::now())?;
+    let minify = config
+        .site
+        .minify
+        .unwrap_or(options.profile == Profile::Production);
+    MinifyOptions::set(db, minify)?;
+
+    let mut standalone = Vec::new();
+    let published = crate::site::published_pages(db);
+    for page in lookup(db, "published_pages", (), published).await? {
+        if page.file.path(db)?.filename() == "_index.md" {
+            // Rendered as part of the section listing
+            continue;
+        }
+        standalone.push(page);
+    }
+
+    let rendered_before = config.rendered_pages.load(Ordering::Relaxed);
+    let files: Vec<_> = standalone.iter().map(|page| page.file).collect();
+    let rendered = for_each_page(db, &files, options.jobs(), |db, file| async move {
+        let db = &*db;
+        let outputs = crate::minify::minified_page(db, file);
+        let outputs = lookup(db, "minified_page", file, outputs).await?;
+        let chonk = crate::db::render_chonk(db, file);
+        let chonk = lookup(db, "render_chonk", file, chonk).await?;
+        PicanteResult::Ok((outputs, chonk))
+    })
+    .await?;
+
+    let mut files = Vec::new();
+    let mut manifest = Manifest::new(&config.site.root);
+    for (page_outputs, chonk) in rendered {
+        for file in &page_outputs {
+            match &file.contents {
+                OutputContents::Copied(asset) => {
+                    manifest.record(file, asset, []);
+                    manifest.record_asset(asset, &file.path);
+                }
+                OutputContents::Generated(_) => manifest.record(
+                    file,
+                    &chonk.og_srcpath,
+                    chonk
+                        .assets
+                        .iter()
+                        .chain(&chonk.shortcode_templates)
+                        .cloned(),
+                ),
+            }
+        }
+        files.extend(page_outputs);
+    }
+    output.pages_rendered = config.rendered_pages.load(Ordering::Relaxed) - rendered_before;
+    output.pages_reused = standalone.len() - output.pages_rendered.min(standalone.len());
+    let listings = crate::minify::minified_listings(db);
+    let listings = lookup(db, "minified_listings", (), listings).await?;
+    for file in &listings {
+        // Assets of section intros, the listings themselves are built from many pages
+        if let OutputContents::Copied(asset) = &file.contents {
+            manifest.record(file, asset, []);
+            manifest.record_asset(asset, &file.path);
+        }
+    }
+    files.extend(listings);
+    let sitemap = crate::sitemap::sitemap(db);
+    files.extend(lookup(db, "sitemap", (), sitemap).await?);
+    let search_index = crate::search::search_index(db);
+    files.extend(lookup(db, "search_index", (), search_index).await?);
+
+    for OutputFile { path, contents } in files {
+        let contents = match contents {
+            OutputContents::Generated(contents) => contents,
+            OutputContents::Copied(src) => config
+                .vfs
+                .read(&src)
+                .wrap_err_with(|| format!("Failed to read {} for {}", src.display(), path))?,
+        };
+        output.files.insert(path, contents);
+    }
+    let OutputFile { path, contents } = manifest.finish(&output.files);
+    if let OutputContents::Generated(contents) = contents {
+        output.files.insert(path, contents);
+    }
+
+    let files: Vec<_> = pages.iter().map(|page| page.file).collect();
+    let diagnostics = for_each_page(db, &files, options.jobs(), |db, file| async move {
+        let db = &*db;
+        let diagnostics = crate::diagnostics::page_diagnostics(db, file);
+        lookup(db, "page_diagnostics", file, diagnostics).await
+    })
+    .await?;
+    output.diagnostics = diagnostics.into_iter().flatten().collect();
+    Ok(output)
+}
+
+/// Runs `f` on each page as a task of its own, at most `jobs` at once, so that pages are rendered
+/// by every worker of the runtime rather than by the task of the build. The results are in the
+/// order of `pages`, whichever finishes first.
+async fn for_each_page<T, F, Fut>(
+    db: &Arc<AaskaDb>,
+    pages: &[SourceFile],
+    jobs: usize,
+    f: F,
+) -> PicanteResult<Vec<T>>
+where
+    T: Send + 'static,
+    F: Fn(Arc<AaskaDb>, SourceFile) -> Fut,
+    Fut: Future<Output = PicanteResult<T>> + Send + 'static,
+{
+    let permits = Arc::new(Semaphore::new(jobs));
+    let mut tasks = JoinSet::new();
+    for (i, page) in pages.iter().enumerate() {
+        let permit = permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        let task = f(db.clone(), *page);
+        tasks.spawn(async move {
+            let result = task.await;
+            drop(permit);
+            (i, result)
+        });
+    }
+
+    let mut results: Vec<Option<T>> = pages.iter().map(|_| None).collect();
+    while let Some(joined) = tasks.join_next().await {
+        let (i, result) = match joined {
+            Ok(joined) => joined,
+            // A panic in a page is resumed here, as it was before pages had tasks of their own
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        };
+        results[i] = Some(result?);
+    }
+    Ok(results
+        .into_iter()
+        .map(|result| result.expect("every page has a result"))
+        .collect())
+}
+
+/// File of the output directory listing what the last [`write`] wrote, one path per line.
+pub const WRITTEN_FILES: &str = ".aaska-outputs";
+
+/// What [`write`] did to the output directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteSummary {
+    pub written: usize,
+    pub unchanged: usize,
+    pub deleted: usize,
+}
+
+/// Writes the output of a build to `dir`. Files that already have the right contents are left
+/// alone, and files written by the previous call that are no longer part of the output are
+/// deleted. Other files of `dir` are never touched.
+pub fn write(output: &BuildOutput, dir: &Path) -> Result<WriteSummary> {
+    let mut summary = WriteSummary::default();
+    for (path, contents) in &output.files {
+        let path = dir.join(path);
+        if std::fs::read(&path).is_ok_and(|existing| existing == *contents) {
+            summary.unchanged += 1;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .wrap_err_with(|| format!("Failed to create directory {}", parent.display()))?;
+        }
+        std::fs::write(&path, contents)
+            .wrap_err_with(|| format!("Failed to write output {}", path.display()))?;
+        summary.written += 1;
+    }
+
+    let list_path = dir.join(WRITTEN_FILES);
+    let previous = std::fs::read_to_string(&list_path).unwrap_or_default();
+    for stale in previous
+        .lines()
+        .filter(|path| is_output_path(path) && !output.files.contains_key(*path))
+    {
+        let path = dir.join(stale);
+        match std::fs::remove_file(&path) {
+            Ok(()) => summary.deleted += 1,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(e).wrap_err_with(|| format!("Failed to delete {}", path.display()));
+            }
+        }
+        // Removes the directories left empty, stopping at the first one that is not
+        for parent in path.ancestors().skip(1) {
+            if parent == dir || std::fs::remove_dir(parent).is_err() {
+                break;
+            }
+        }
+    }
+
+    let list: String = output
+        .files
+        .keys()
+        .map(|path| format!("{}\n", path))
+        .collect();
+    if previous != list {
+        std::fs::write(&list_path, list)
+            .wrap_err_with(|| format!("Failed to write {}", list_path.display()))?;
+    }
+    Ok(summary)
+}
+
+/// Guards against a tampered list making [`write`] delete files outside of the output directory.
+fn is_output_path(path: &str) -> bool {
+    !path.is_empty()
+        && !Path::new(path).is_absolute()
+        && Path::new(path)
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::MemoryVfs;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_build_again() {
+        let vfs = Arc::new(MemoryVfs::new());
+        vfs.insert(
+            "/site/content/post.md",
+            "![cat](cat.png)\n\n{{< greet >}}\n",
+        );
+        vfs.insert("/site/content/cat.png", "first cat");
+        vfs.insert("/site/templates/shortcodes/greet.html", "<b>hello</b>");
+        let db = Arc::new(AaskaDb::open("/site", vfs.clone()).unwrap());
+        let options = BuildOptions {
+            profile: Profile::Dev,
+            ..Default::default()
+        };
+        let page = |output: &BuildOutput| {
+            String::from_utf8_lossy(&output.files["post/index.html"]).into_owned()
+        };
+        let asset = |output: &BuildOutput| {
+            let (path, contents) = output
+                .files
+                .iter()
+                .find(|(path, _)| path.starts_with("post/cat."))
+                .expect("the image is published");
+            (path.clone(), contents.clone())
+        };
+
+        let first = build(&db, options).await.unwrap();
+        assert!(page(&first).contains("<b>hello</b>"));
+        let (first_asset, _) = asset(&first);
+
+        vfs.insert(
+            "/site/content/post.md",
+            "![cat](cat.png)\n\n{{< greet >}}\n\nEdited\n",
+        );
+        vfs.insert("/site/content/cat.png", "second cat");
+        vfs.insert("/site/templates/shortcodes/greet.html", "<b>bye</b>");
+        let second = build(&db, options).await.unwrap();
+        assert!(page(&second).contains("Edited"));
+        assert!(page(&second).contains("<b>bye</b>"));
+
+        // The image is published under the hash of its new contents, which the page links to
+        let (second_asset, contents) = asset(&second);
+        assert_ne!(first_asset, second_asset);
+        assert_eq!(contents, b"second cat");
+        assert!(page(&second).contains(&second_asset["post/".len()..]));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_shortcode_templates() {
+        let vfs = Arc::new(MemoryVfs::new());
+        vfs.insert(
+            "/site/content/post.md",
+            "{{< box >}}\nSee [the other page](other.md).\n{{< /box >}}\n",
+        );
+        vfs.insert("/site/content/other.md", "# Other\n");
+        let db = Arc::new(AaskaDb::open("/site", vfs.clone()).unwrap());
+        let options = BuildOptions {
+            profile: Profile::Dev,
+            ..Default::default()
+        };
+
+        let first = build(&db, options).await.unwrap();
+        assert_eq!(first.error_count(), 1, "{:?}", first.diagnostics);
+
+        // Adding the template re-renders the page, and its body links like the rest of the page
+        vfs.insert(
+            "/site/templates/shortcodes/box.html",
+            "<div class=box>{{ body }}</div>",
+        );
+        let second = build(&db, options).await.unwrap();
+        assert_eq!(second.error_count(), 0, "{:?}", second.diagnostics);
+        let page = String::from_utf8_lossy(&second.files["post/index.html"]).into_owned();
+        assert!(
+            page.contains("<div class=box><p>See <a href=\"/other/\">the other page</a>.</p>"),
+            "{}",
+            page
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_links_to_site_urls() {
+        let vfs = Arc::new(MemoryVfs::new());
+        vfs.insert(
+            "/site/content/blog/post.md",
+            "---\ntags: [rust]\n---\nMore on [rust](../tags/rust/), [the blog](../blog) and \
+             [older posts](page/2/).\n",
+        );
+        let db = Arc::new(AaskaDb::open("/site", vfs).unwrap());
+        let options = BuildOptions {
+            profile: Profile::Dev,
+            ..Default::default()
+        };
+
+        let output = build(&db, options).await.unwrap();
+        assert_eq!(output.error_count(), 0, "{:?}", output.diagnostics);
+        let page = String::from_utf8_lossy(&output.files["blog/post/index.html"]).into_owned();
+        assert!(page.contains("href=\"../tags/rust/\""), "{}", page);
+        assert!(output.files.contains_key("tags/rust/index.html"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_url_conflicts() {
+        let vfs = Arc::new(MemoryVfs::new());
+        vfs.insert("/site/content/blog/index.md", "# Blog\n");
+        vfs.insert("/site/content/blog/_index.md", "# All posts\n");
+        vfs.insert("/site/content/blog/post.md", "# Post\n");
+        let db = Arc::new(AaskaDb::open("/site", vfs).unwrap());
+        let options = BuildOptions {
+            profile: Profile::Dev,
+            ..Default::default()
+        };
+
+        let output = build(&db, options).await.unwrap();
+        assert_eq!(output.error_count(), 2, "{:?}", output.diagnostics);
+        let conflict = output
+            .diagnostics
+            .iter()
+            .find(|d| d.file.rel_path() == "blog/index.md")
+            .unwrap();
+        assert_eq!(
+            conflict.message,
+            "Page is published at /blog/ like blog/_index.md, only one of them can be written there"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_pages_in_parallel() {
+        let vfs = Arc::new(MemoryVfs::new());
+        let text = "Some *text* to render.\n\n".repeat(200);
+        for i in 0..64 {
+            let body = format!("# Page {}\n\n{}", i, text);
+            vfs.insert(format!("/site/content/page-{}.md", i), body);
+        }
+        let db = Arc::new(AaskaDb::open("/site", vfs).unwrap());
+        crate::timings::enable(&db);
+        let options = BuildOptions {
+            jobs: 4,
+            profile: Profile::Dev,
+            ..Default::default()
+        };
+
+        let output = build(&db, options).await.unwrap();
+        assert_eq!(output.pages_rendered, 64);
+        let threads: std::collections::HashSet<_> = crate::timings::take(&db)
+            .into_iter()
+            .filter(|timing| timing.query == "render_chonk")
+            .map(|timing| timing.thread)
+            .collect();
+        assert!(threads.len() > 1, "{:?}", threads);
+    }
+
+    #[test]
+    fn test_write() {
+        let dir = std::env::temp_dir().join(format!("aaska-write-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let output = |files: &[(&str, &str)]| BuildOutput {
+            files: files
+                .iter()
+                .map(|(path, contents)| (path.to_string(), contents.as_bytes().to_vec()))
+                .collect(),
+            ..Default::default()
+        };
+
+        let first = output(&[("index.html", "home"), ("blog/post/index.html", "post")]);
+        let summary = write(&first, &dir).unwrap();
+        assert_eq!(
+            (summary.written, summary.unchanged, summary.deleted),
+            (2, 0, 0)
+        );
+
+        std::fs::write(dir.join("CNAME"), "example.com").unwrap();
+        let second = output(&[("index.html", "new home")]);
+        let summary = write(&second, &dir).unwrap();
+        assert_eq!(
+            (summary.written, summary.unchanged, summary.deleted),
+            (1, 0, 1)
+        );
+        assert!(!dir.join("blog").exists());
+        assert!(dir.join("CNAME").exists());
+
+        let summary = write(&second, &dir).unwrap();
+        assert_eq!(
+            (summary.written, summary.unchanged, summary.deleted),
+            (0, 1, 0)
+        );
+
+        assert!(!is_output_path("../outside"));
+        assert!(!is_output_path("/etc/passwd"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,222 @@
+//! Listing pages: sections (a directory with an `_index.md`) and taxonomy terms.
+
+use crate::config::SiteConfig;
+use crate::db::{Db, SourceFile, render_chonk};
+use crate::paginate::{PageRef, Pager, pager_url, paginate, sort_pages};
+use crate::site::{PageMeta, output_path, page_meta, published_pages};
+use crate::{OutputContents, OutputFile};
+use picante::PicanteResult;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Section {
+    pub file: SourceFile,
+    pub title: String,
+    pub url: String,
+    pub paginate_by: Option<usize>,
+    /// Pages directly inside the section directory, sorted for listing
+    pub pages: Vec<PageRef>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Taxonomy {
+    pub name: String,
+    pub url: String,
+    pub paginate_by: Option<usize>,
+    pub terms: Vec<Term>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Term {
+    pub name: String,
+    pub url: String,
+    pub pages: Vec<PageRef>,
+}
+
+async fn all_page_metas<DB: Db>(db: &DB) -> PicanteResult<Vec<(SourceFile, PageMeta)>> {
+    let pages = published_pages(db).await?;
+    let mut metas = Vec::with_capacity(pages.len());
+    for page in pages {
+        metas.push((page.file, page_meta(db, page.file).await?));
+    }
+    Ok(metas)
+}
+
+#[picante::tracked]
+pub async fn sections<DB: Db>(db: &DB) -> PicanteResult<Vec<Section>> {
+    let _timing = crate::timings::query(db, "sections", ());
+    let metas = all_page_metas(db).await?;
+
+    let mut sections = Vec::new();
+    for (file, section) in metas.iter().filter(|(_, meta)| meta.is_section()) {
+        let dir = section.src.rel_dir();
+        let mut pages: Vec<PageRef> = metas
+            .iter()
+            .map(|(_, meta)| meta)
+            .filter(|meta| !meta.is_section() && meta.url != section.url)
+            .filter(|meta| meta.src.rel_dir() == dir)
+            .map(PageRef::from)
+            .collect();
+        sort_pages(&mut pages);
+
+        sections.push(Section {
+            file: *file,
+            title: section.title().to_string(),
+            url: section.url.clone(),
+            paginate_by: section.front_matter.paginate_by,
+            pages,
+        });
+    }
+    sections.sort_by(|a, b| a.url.cmp(&b.url));
+    Ok(sections)
+}
+
+#[picante::tracked]
+pub async fn taxonomies<DB: Db>(db: &DB) -> PicanteResult<Vec<Taxonomy>> {
+    let _timing = crate::timings::query(db, "taxonomies", ());
+    let metas = all_page_metas(db).await?;
+
+    let mut taxonomies = Vec::new();
+    for config in &crate::config(db).site.taxonomies {
+        let url = format!("/{}/", slugify(&config.name));
+        let mut terms: BTreeMap<String, Term> = BTreeMap::new();
+        for (_, meta) in metas.iter().filter(|(_, meta)| !meta.is_section()) {
+            for name in meta.front_matter.terms(&config.name) {
+                let slug = slugify(&name);
+                terms
+                    .entry(slug.clone())
+                    .or_insert_with(|| Term {
+                        name,
+                        url: format!("{}{}/", url, slug),
+                        pages: Vec::new(),
+                    })
+                    .pages
+                    .push(PageRef::from(meta));
+            }
+        }
+
+        let mut terms: Vec<Term> = terms.into_values().collect();
+        for term in terms.iter_mut() {
+            sort_pages(&mut term.pages);
+        }
+        taxonomies.push(Taxonomy {
+            name: config.name.clone(),
+            url,
+            paginate_by: config.paginate_by,
+            terms,
+        });
+    }
+    Ok(taxonomies)
+}
+
+/// Renders every section and taxonomy listing, split into pages where configured.
+#[picante::tracked]
+pub async fn list_pages<DB: Db>(db: &DB) -> PicanteResult<Vec<OutputFile>> {
+    let _timing = crate::timings::query(db, "list_pages", ());
+    let config = crate::config(db);
+    let site = &config.site;
+    let mut outputs = Vec::new();
+
+    for section in sections(db).await? {
+        let intro = render_chonk(db, section.file).await?;
+        let pagers = paginate(&section.url, section.pages, section.paginate_by);
+        push_listing(
+            &mut outputs,
+            site,
+            &section.title,
+            &intro.html,
+            &section.url,
+            pagers,
+        );
+        // The assets of the intro are published next to the first page, which shows it
+        let html_path = output_path(&section.url);
+        let dir = &html_path[..html_path.len() - "index.html".len()];
+        for (src, hashed_name) in intro.hashed_assets {
+            outputs.push(OutputFile {
+                path: format!("{}{}", dir, hashed_name),
+                contents: OutputContents::Copied(src),
+            });
+        }
+    }
+
+    for taxonomy in taxonomies(db).await? {
+        let index = Pager {
+            index: 1,
+            total: 1,
+            url: taxonomy.url.clone(),
+            pages: taxonomy
+                .terms
+                .iter()
+                .map(|term| PageRef {
+                    title: term.name.clone(),
+                    url: term.url.clone(),
+                    date: None,
+                })
+                .collect(),
+            first: taxonomy.url.clone(),
+            last: taxonomy.url.clone(),
+            prev: None,
+            next: None,
+        };
+        outputs.push(OutputFile::generated(
+            output_path(&taxonomy.url),
+            crate::layout::list(site, &taxonomy.name, "", &index),
+        ));
+
+        for term in taxonomy.terms {
+            let pagers = paginate(&term.url, term.pages, taxonomy.paginate_by);
+            push_listing(&mut outputs, site, &term.name, "", &term.url, pagers);
+        }
+    }
+
+    Ok(outputs)
+}
+
+fn push_listing(
+    outputs: &mut Vec<OutputFile>,
+    site: &SiteConfig,
+    title: &str,
+    intro: &str,
+    base: &str,
+    pagers: Vec<Pager>,
+) {
+    if pagers.len() > 1 {
+        // `page/1/` is never linked to, but is a natural URL to guess
+        outputs.push(OutputFile::generated(
+            output_path(&format!("{}page/1/", base)),
+            crate::layout::redirect(site, &pager_url(base, 1)),
+        ));
+    }
+    for pager in pagers {
+        // Only the first page shows the section content
+        let intro = if pager.index == 1 { intro } else { "" };
+        outputs.push(OutputFile::generated(
+            output_path(&pager.url),
+            crate::layout::list(site, title, intro, &pager),
+        ));
+    }
+}
+
+/// Turns a term into a URL path segment: lowercase alphanumerics separated by dashes.
+pub fn slugify(s: &str) -> String {
+    let mut slug = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_slugify() {
+        use super::slugify;
+        assert_eq!(slugify("Rust"), "rust");
+        assert_eq!(slugify("Web Development"), "web-development");
+        assert_eq!(slugify("  C++ / WASM "), "c-wasm");
+    }
+}
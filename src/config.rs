@@ -0,0 +1,324 @@
+//! Site configuration, read from `aaska.toml` at the root of the site.
+
+use eyre::{Context, Result, bail};
+use std::collections::BTreeMap;
+
+use crate::path::Sandbox;
+use crate::vfs::{DiskVfs, Vfs};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SiteConfig {
+    /// Absolute URL the site is served from, e.g. `https://example.com`. Used wherever a full URL
+    /// is required, such as in `sitemap.xml`.
+    pub base_url: String,
+    /// Front-matter keys pages are classified by, each getting a listing page per term
+    pub taxonomies: Vec<TaxonomyConfig>,
+    pub search: SearchConfig,
+    /// Directories root-relative links can point into, by URL prefix, as in
+    /// `"/static" = "static"`. Other root-relative links resolve against the content directory.
+    /// Directories are relative to the site root, and absolute once loaded.
+    pub mounts: BTreeMap<String, PathBuf>,
+    pub sandbox: SandboxConfig,
+    /// Minify HTML output. By default it is, except in dev builds.
+    pub minify: Option<bool>,
+    pub external_links: ExternalLinksConfig,
+    pub alerts: AlertsConfig,
+    /// Directory of the site, set when loading the configuration
+    #[serde(skip)]
+    pub root: PathBuf,
+}
+
+impl Default for SiteConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "http://localhost".to_string(),
+            taxonomies: vec![TaxonomyConfig {
+                name: "tags".to_string(),
+                paginate_by: None,
+            }],
+            search: SearchConfig::default(),
+            mounts: BTreeMap::new(),
+            sandbox: SandboxConfig::default(),
+            minify: None,
+            external_links: ExternalLinksConfig::default(),
+            alerts: AlertsConfig::default(),
+            root: PathBuf::new(),
+        }
+    }
+}
+
+/// Where source files may be read from. The site root is always allowed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    /// Other directories files may be read from, relative to the site root
+    pub allow: Vec<PathBuf>,
+    /// Also reject symlinks pointing outside of the allowed directories
+    pub symlinks: bool,
+    /// Read files from anywhere, for sites whose content is fully trusted
+    pub unrestricted: bool,
+}
+
+/// Attributes added to links leaving the site, that is absolute URLs to another host than the
+/// one of `base_url`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ExternalLinksConfig {
+    /// Values of the `rel` attribute, such as `noopener`, `noreferrer`, `nofollow` or `external`
+    pub rel: Vec<String>,
+    /// Open the links in a new tab, with `target="_blank"`
+    pub target_blank: bool,
+    /// Class given to the links, for styling
+    pub class: Option<String>,
+    /// If not empty, only links to these domains get the attributes. A domain also covers its
+    /// subdomains.
+    pub include: Vec<String>,
+    /// Links to these domains never get the attributes, as for other sites you own
+    pub exclude: Vec<String>,
+}
+
+impl ExternalLinksConfig {
+    /// Whether a link to `url`, from a site served at `base_url`, gets the attributes.
+    pub fn applies_to(&self, url: &str, base_url: &str) -> bool {
+        let Some(host) = url_host(url) else {
+            return false;
+        };
+        let matches = |domains: &[String]| {
+            domains.iter().any(|domain| {
+                let domain = domain.trim_start_matches("*.").trim_start_matches('.');
+                host.eq_ignore_ascii_case(domain)
+                    || host
+                        .to_ascii_lowercase()
+                        .ends_with(&format!(".{}", domain.to_ascii_lowercase()))
+            })
+        };
+        url_host(base_url).is_none_or(|site| !site.eq_ignore_ascii_case(&host))
+            && (self.include.is_empty() || matches(&self.include))
+            && !matches(&self.exclude)
+    }
+}
+
+/// Host of an absolute `http(s)` or protocol-relative URL.
+fn url_host(url: &str) -> Option<String> {
+    let lower = url.get(..8).unwrap_or(url).to_ascii_lowercase();
+    let rest = if lower.starts_with("https://") {
+        &url[8..]
+    } else if lower.starts_with("http://") {
+        &url[7..]
+    } else {
+        url.strip_prefix("//")?
+    };
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = match host.rfind(':') {
+        // Leave the colons of IPv6 addresses alone
+        Some(colon) if !host[colon..].contains(']') => &host[..colon],
+        _ => host,
+    };
+    (!host.is_empty()).then(|| host.to_string())
+}
+
+/// GitHub-style alerts, blockquotes starting with a marker such as `> [!NOTE]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct AlertsConfig {
+    /// Show an icon before the title of alerts
+    pub icons: bool,
+    /// Kinds of alerts by name, lowercased on load. Entries for the kinds GitHub supports (`note`,
+    /// `tip`, `important`, `warning` and `caution`) change their title or icon, other entries add
+    /// kinds.
+    pub kinds: BTreeMap<String, AlertKindConfig>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct AlertKindConfig {
+    /// Title shown at the top of the alert, by default the name of the kind
+    pub title: Option<String>,
+    /// HTML of the icon, such as an inline SVG
+    pub icon: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SearchConfig {
+    /// Write a search index to `search/`
+    pub enabled: bool,
+    /// Number of leading characters of a token that select its shard
+    pub prefix_len: usize,
+    /// Also write `search/search.js`, a small client for the index
+    pub client: bool,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            prefix_len: 2,
+            client: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct TaxonomyConfig {
+    /// Front-matter key holding the terms, also used as the URL prefix (`/tags/rust/`)
+    pub name: String,
+    /// Number of pages per term listing page
+    #[serde(default)]
+    pub paginate_by: Option<usize>,
+}
+
+impl SiteConfig {
+    /// Reads `aaska.toml` from the site root.
+    pub fn load(root: impl AsRef<Path>) -> Result<Self> {
+        Self::load_from(&DiskVfs, root)
+    }
+
+    /// Reads `aaska.toml` from the site root, through `vfs`.
+    pub fn load_from(vfs: &dyn Vfs, root: impl AsRef<Path>) -> Result<Self> {
+        let root = root.as_ref();
+        let mut config = Self::read(vfs, &root.join("aaska.toml"))?;
+        config.root = root.to_path_buf();
+
+        let mut mounts = BTreeMap::new();
+        for (prefix, dir) in std::mem::take(&mut config.mounts) {
+            let dir = crate::path::normalize_path(root.join(dir));
+            if !dir.starts_with(root) {
+                bail!(
+                    "Mount {} points to {}, outside of the site",
+                    prefix,
+                    dir.display()
+                );
+            }
+            mounts.insert(format!("/{}", prefix.trim_matches('/')), dir);
+        }
+        config.mounts = mounts;
+
+        // Alerts are looked up by their lowercased kind, `[!NOTE]` as well as `[!Note]`
+        let mut kinds = BTreeMap::new();
+        for (kind, alert) in std::mem::take(&mut config.alerts.kinds) {
+            let name = kind.to_lowercase();
+            if kinds.insert(name.clone(), alert).is_some() {
+                bail!("Alert kind {} is configured more than once", name);
+            }
+        }
+        config.alerts.kinds = kinds;
+        Ok(config)
+    }
+
+    /// Reads the configuration file. A missing file results in the default configuration.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::read(&DiskVfs, path.as_ref())
+    }
+
+    fn read(vfs: &dyn Vfs, path: &Path) -> Result<Self> {
+        if !vfs.is_file(path) {
+            return Ok(Self::default());
+        }
+        let contents = vfs
+            .read(path)
+            .wrap_err_with(|| format!("Failed to read config file {}", path.display()))?;
+        let contents = std::str::from_utf8(&contents)
+            .wrap_err_with(|| format!("Config file {} is not valid UTF-8", path.display()))?;
+        toml::from_str(contents)
+            .wrap_err_with(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    /// The directories files may be read from. Without a site root, as when the configuration
+    /// is not loaded from a site, the site is the current directory.
+    pub fn sandbox(&self, vfs: &dyn Vfs) -> Sandbox {
+        if self.sandbox.unrestricted {
+            return Sandbox::unrestricted();
+        }
+        let root = crate::path::soft_cannonicalize_cwd(&self.root);
+        let roots = std::iter::once(root.clone())
+            .chain(self.sandbox.allow.iter().map(|dir| root.join(dir)));
+        Sandbox::new(roots, self.sandbox.symlinks, vfs)
+    }
+
+    /// Directory holding the markdown pages.
+    pub fn content_dir(&self) -> PathBuf {
+        self.root.join("content")
+    }
+
+    /// Directory holding site templates, such as `shortcodes/<name>.html`.
+    pub fn templates_dir(&self) -> PathBuf {
+        self.root.join("templates")
+    }
+
+    /// Joins a site-relative URL (`/blog/post/`) with the base URL.
+    pub fn absolute_url(&self, url: &str) -> String {
+        format!(
+            "{}/{}",
+            self.base_url.trim_end_matches('/'),
+            url.trim_start_matches('/')
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_external_links() {
+        let base_url = "https://example.com";
+        let mut links = ExternalLinksConfig {
+            rel: vec!["noopener".to_string()],
+            ..Default::default()
+        };
+        assert!(links.applies_to("https://rust-lang.org/learn", base_url));
+        assert!(links.applies_to("//cdn.net/lib.js", base_url));
+        assert!(links.applies_to("http://user@Docs.RS:8080?q", base_url));
+        assert!(!links.applies_to("https://EXAMPLE.com/about/", base_url));
+        assert!(!links.applies_to("/blog/post/", base_url));
+        assert!(!links.applies_to("post.md#intro", base_url));
+        assert!(!links.applies_to("mailto:me@rust-lang.org", base_url));
+
+        links.exclude = vec!["rust-lang.org".to_string()];
+        assert!(!links.applies_to("https://blog.rust-lang.org/", base_url));
+        assert!(links.applies_to("https://docs.rs/", base_url));
+        assert!(links.applies_to("https://trust-lang.org/", base_url));
+
+        links.include = vec!["*.github.io".to_string()];
+        assert!(links.applies_to("https://someone.github.io/", base_url));
+        assert!(!links.applies_to("https://docs.rs/", base_url));
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let vfs = crate::vfs::MemoryVfs::new();
+        let mut config = SiteConfig::default();
+        let sandbox = config.sandbox(&vfs);
+        assert!(sandbox.check(Path::new("content/post.md"), &vfs).is_ok());
+        assert!(sandbox.check(Path::new("../outside.md"), &vfs).is_err());
+        assert!(sandbox.check(Path::new("/etc/passwd"), &vfs).is_err());
+
+        config.sandbox.unrestricted = true;
+        let sandbox = config.sandbox(&vfs);
+        assert!(sandbox.check(Path::new("/etc/passwd"), &vfs).is_ok());
+    }
+
+    #[test]
+    fn test_alert_kinds() {
+        let vfs = crate::vfs::MemoryVfs::new();
+        vfs.insert(
+            "/site/aaska.toml",
+            "[alerts.kinds.Example]\ntitle = \"E.g.\"\n",
+        );
+        let config = SiteConfig::load_from(&vfs, "/site").unwrap();
+        assert_eq!(
+            config.alerts.kinds["example"].title.as_deref(),
+            Some("E.g.")
+        );
+
+        vfs.insert(
+            "/site/aaska.toml",
+            "[alerts.kinds.note]\ntitle = \"a\"\n[alerts.kinds.NOTE]\ntitle = \"b\"\n",
+        );
+        assert!(SiteConfig::load_from(&vfs, "/site").is_err());
+    }
+}
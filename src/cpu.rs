@@ -0,0 +1,36 @@
+//! CPU-heavy work, such as hashing assets, reading the size of images and minifying pages, runs on
+//! the rayon pool rather than on the async workers, so that a large image does not hold up every
+//! other query. The size of the pool is rayon's, which the CLI sets from `--jobs`.
+
+use futures::channel::oneshot;
+
+/// Runs `f` on the rayon pool and waits for its result. A panic in `f` is resumed in the caller,
+/// where the query engine reports it like any other.
+pub async fn run<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    let (tx, rx) = oneshot::channel();
+    rayon::spawn(move || {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+        // The caller may have stopped waiting, in which case there is nobody to tell
+        let _ = tx.send(result);
+    });
+    match rx.await.expect("rayon dropped a task") {
+        Ok(value) => value,
+        Err(panic) => std::panic::resume_unwind(panic),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let sum = futures::executor::block_on(run(|| (1..=100u32).sum::<u32>()));
+        assert_eq!(sum, 5050);
+
+        let panicked = std::panic::catch_unwind(|| {
+            futures::executor::block_on(run(|| panic!("boom")));
+        });
+        assert!(panicked.is_err());
+    }
+}
@@ -1,11 +1,24 @@
-use crate::{Chonk, SrcPath, internal_prelude::*};
-use std::path::PathBuf;
-
+use crate::backlinks::PageLink;
+use crate::diagnostics::{Diagnostic, Span};
+use crate::encoding::DecodeError;
+use crate::path::LinkDest;
+use crate::shortcode::ShortcodeCall;
+use crate::site::PageInput;
+use crate::{Chonk, Heading, SrcPath, internal_prelude::*};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::config::SiteConfig;
+use crate::html::{DefaultRenderer, HtmlRenderer};
+use crate::shortcode::ShortcodeRegistry;
+use crate::vfs::{DiskVfs, Vfs};
+use crate::wikilink::WikiTarget;
 use dashmap::DashMap;
 use dashmap::mapref::entry::Entry;
-use eyre::{Context, Report, Result};
+use eyre::{Context, Result, eyre};
 use picante::PicanteResult;
-use pulldown_cmark::{Event, Parser, Tag};
+use pulldown_cmark::{CowStr, Event, HeadingLevel, LinkType, Parser, Tag, TagEnd};
 
 #[picante::input]
 pub struct SourceFile {
@@ -15,18 +28,32 @@ pub struct SourceFile {
 }
 
 impl SourceFile {
+    /// Reads a file, which must be inside the site's sandbox.
     pub fn from_disk<DB: Db>(db: &DB, path: SrcPath) -> Result<Self> {
-        SourceFile::new(
+        let config = crate::config(db);
+        config.sandbox.check(&path, config.vfs.as_ref())?;
+        let file = SourceFile::new(
             db,
             path.clone(),
-            std::fs::read(&path).wrap_err_with(|| {
+            config.vfs.read(&path).wrap_err_with(|| {
                 format!("Failed to read file from disk at path {}", path.display())
             })?,
         )
-        .wrap_err_with(|| format!("Failed to create SourceFile for path {}", path.display()))
+        .wrap_err_with(|| format!("Failed to create SourceFile for path {}", path.display()))?;
+        config.sources.lock().unwrap().insert(path);
+        Ok(file)
     }
 }
 
+/// Last modification time of a source file, in seconds since the unix epoch. Kept apart from
+/// [`SourceFile`] so that touching a file does not invalidate everything that reads its contents.
+#[picante::input]
+pub struct SourceMtime {
+    #[key]
+    pub path: SrcPath,
+    pub modified: i64,
+}
+
 /// Source of an asset, a path, not loaded, with a cannonical path
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
@@ -34,29 +61,146 @@ struct ParsedMdHash(Vec<u8>);
 
 // Picante database with custom fields for caching
 #[picante::db(
-    inputs(SourceFile),
-    tracked(render_chonk, process_asset, process_md),
+    inputs(
+        SourceFile,
+        SourceMtime,
+        crate::site::ContentSet,
+        crate::site::PublishOptions,
+        crate::minify::MinifyOptions,
+        crate::shortcode::ShortcodeTemplates
+    ),
+    tracked(
+        source_text,
+        render_chonk,
+        page_headings,
+        process_asset,
+        process_md,
+        crate::site::page_meta,
+        crate::site::is_published,
+        crate::site::published_pages,
+        crate::site::excluded_pages,
+        crate::site::url_conflicts,
+        crate::site::render_page,
+        crate::sitemap::sitemap,
+        crate::collections::sections,
+        crate::collections::taxonomies,
+        crate::collections::list_pages,
+        crate::wikilink::wikilink_index,
+        crate::wikilink::resolve_wikilink,
+        crate::search::search_entry,
+        crate::search::search_index,
+        crate::backlinks::page_links,
+        crate::backlinks::backlink_index,
+        crate::backlinks::backlinks,
+        crate::diagnostics::page_diagnostics,
+        crate::minify::minified_page,
+        crate::image::image_size,
+        crate::minify::minified_listings
+    ),
     db_trait(Db)
 )]
 pub struct AaskaDb {
     pub in_mem_assets: DashMap<SrcPath, SourceFile>,
+    config: crate::ConfigHandle,
 }
 
 impl AaskaDb {
+    /// A database with the default configuration, reading from disk.
     pub fn new_simple() -> Self {
-        Self::new(DashMap::new())
+        Self::with_config(
+            SiteConfig::default(),
+            ShortcodeRegistry::with_builtins(),
+            Arc::new(DefaultRenderer),
+            Arc::new(DiskVfs),
+        )
+    }
+
+    /// A database building `site`, writing the HTML of pages with `renderer`. Every source file
+    /// is read through `vfs`.
+    pub fn with_config(
+        site: SiteConfig,
+        shortcodes: ShortcodeRegistry,
+        renderer: Arc<dyn HtmlRenderer>,
+        vfs: Arc<dyn Vfs>,
+    ) -> Self {
+        let db = Self::new(DashMap::new(), crate::ConfigHandle::new());
+        db.config
+            .register(&db, crate::Config::new(site, shortcodes, renderer, vfs));
+        db
+    }
+
+    /// A database building the site at `root`, configured by its `aaska.toml`.
+    pub fn open(root: impl AsRef<Path>, vfs: Arc<dyn Vfs>) -> Result<Self> {
+        let site = SiteConfig::load_from(vfs.as_ref(), root)?;
+        Ok(Self::with_config(
+            site,
+            ShortcodeRegistry::with_builtins(),
+            Arc::new(DefaultRenderer),
+            vfs,
+        ))
     }
 
     pub fn input(&self, path: SrcPath) -> Result<SourceFile> {
         Ok(match self.in_mem_assets.entry(path.clone()) {
             Entry::Occupied(entry) => *entry.get(),
             Entry::Vacant(entry) => {
-                let contents = std::fs::read(&*path)
+                let config = crate::config(self);
+                config.sandbox.check(&path, config.vfs.as_ref())?;
+                let contents = config
+                    .vfs
+                    .read(&path)
                     .wrap_err_with(|| format!("Failed to read {}", &path.display()))?;
-                *entry.insert(SourceFile::new(self, path, contents)?)
+                let file = SourceFile::new(self, path.clone(), contents)?;
+                config.sources.lock().unwrap().insert(path);
+                *entry.insert(file)
             }
         })
     }
+
+    /// Reads every source file loaded so far again, so that the next build sees the changes to
+    /// assets and templates, not only to pages. A file that can no longer be read is emptied, so
+    /// that what used it runs again and reports it.
+    pub fn refresh_sources(&self) -> Result<()> {
+        let config = crate::config(self);
+        let sources: Vec<SrcPath> = config.sources.lock().unwrap().iter().cloned().collect();
+        for path in sources {
+            let contents = config.vfs.read(&path).unwrap_or_default();
+            // Setting an input to the value it already has does not invalidate anything
+            SourceFile::new(self, path, contents)?;
+        }
+        Ok(())
+    }
+
+    /// Loads a markdown page, anchoring its path at the content root. The page is read again
+    /// every time, so that loading it on each build picks up its changes.
+    pub fn load_page(&self, content_root: &Path, path: &Path) -> Result<PageInput> {
+        let rel = path.strip_prefix(content_root).wrap_err_with(|| {
+            format!(
+                "Page {} is not inside the content root {}",
+                path.display(),
+                content_root.display()
+            )
+        })?;
+        let src = SrcPath::try_from_relaxed_path(rel, content_root)?;
+        let config = crate::config(self);
+        config.sandbox.check(&src, config.vfs.as_ref())?;
+        let modified = config
+            .vfs
+            .modified(path)
+            .wrap_err_with(|| format!("Failed to read modification time of {}", path.display()))?;
+        let contents = config
+            .vfs
+            .read(path)
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+        // Setting an input to the value it already has does not invalidate anything
+        let file = SourceFile::new(self, src.clone(), contents)?;
+        self.in_mem_assets.insert(src.clone(), file);
+
+        Ok(PageInput {
+            mtime: SourceMtime::new(self, src, modified)?,
+            file,
+        })
+    }
 }
 
 fn hash_md(md: &[u8]) -> ParsedMdHash {
@@ -67,16 +211,6 @@ fn hash_md(md: &[u8]) -> ParsedMdHash {
     ParsedMdHash(result.to_ascii_lowercase())
 }
 
-// Diagnostic accumulator replaced with simple logging
-fn log_error(file: SourceFile, db: &AaskaDb, err: Report) {
-    let filename = file
-        .path(db)
-        .ok()
-        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
-        .unwrap_or_else(|| "<unknown>".to_string());
-    error!("Error in file {}: {:?}", filename, err);
-}
-
 // ProcessedAsset is now a regular struct
 #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct ProcessedAsset {
@@ -91,63 +225,246 @@ pub struct ParsedMd {
     assets: Vec<SrcPath>,
 }
 
+/// The text of a markdown file, see [`crate::encoding::decode`].
+#[picante::tracked]
+pub async fn source_text<DB: Db>(
+    db: &DB,
+    md_file: SourceFile,
+) -> PicanteResult<Result<String, DecodeError>> {
+    let _timing = crate::timings::query(db, "source_text", md_file);
+    let contents = md_file.contents(db)?;
+    Ok(crate::encoding::decode(&contents).map(|text| text.into_owned()))
+}
+
 #[picante::tracked]
 pub async fn render_chonk<DB: Db>(db: &DB, md_file: SourceFile) -> PicanteResult<Chonk> {
+    let _timing = crate::timings::query(db, "render_chonk", md_file);
     use futures::stream::{FuturesUnordered, StreamExt};
     use std::collections::HashMap;
 
-    let options = crate::config().md_options;
-    let file_contents = md_file.contents(db)?;
-    let file_contents_str = std::str::from_utf8(&file_contents).unwrap();
+    let config = crate::config(db);
+    let options = crate::MD_OPTIONS;
+    let source = match source_text(db, md_file).await? {
+        Ok(source) => source,
+        // Reported by `page_diagnostics`, the page is left out of the build
+        Err(_) => {
+            return Ok(Chonk {
+                html: String::new(),
+                assets: Vec::new(),
+                hashed_assets: Vec::new(),
+                page_links: Vec::new(),
+                shortcode_templates: Vec::new(),
+                headings: Vec::new(),
+                diagnostics: Vec::new(),
+                og_srcpath: (*md_file.path(db)?).clone(),
+            });
+        }
+    };
+    let source = source.as_str();
+    let extracted = crate::shortcode::extract(source);
+    let shortcodes = &extracted.calls;
+    let file_contents_str = extracted.markdown.as_str();
 
     let md_path = md_file.path(db)?;
-    let anchor_path = md_path.as_anchor();
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    // Shortcode bodies are part of the page: their links and images are resolved like the rest
+    let documents = std::iter::once((file_contents_str, None)).chain(
+        shortcodes
+            .iter()
+            .filter_map(|call| Some((call.body.as_deref()?, Some(call.body_offset)))),
+    );
 
     // First pass: collect assets by consuming the parser
-    let parser1 = Parser::new_ext(file_contents_str, options);
     let mut assets = Vec::new();
-    let mut asset_url_map: Vec<(String, SrcPath)> = Vec::new();
-
-    for event in parser1 {
-        match event {
-            Event::Start(tag) => match tag {
-                Tag::Image { dest_url, .. } => {
-                    let original_url = dest_url.to_string();
-                    let asset_path =
-                        SrcPath::from_relaxed_path(PathBuf::from(dest_url.as_ref()), anchor_path);
-                    assets.push(asset_path.clone());
-                    asset_url_map.push((original_url, asset_path));
+    let mut asset_url_map: Vec<(String, SrcPath, Range<usize>)> = Vec::new();
+    let mut page_links: Vec<(String, SrcPath, Range<usize>)> = Vec::new();
+    let mut wikilinks: Vec<(String, Range<usize>)> = Vec::new();
+    let mut found_html = false;
+    let mounts = &config.site.mounts;
+    // Text of the innermost block, and the links in it with their offset, for backlink snippets
+    let mut block_text = String::new();
+    let mut block_links: Vec<(String, usize)> = Vec::new();
+    let mut snippets: HashMap<String, String> = HashMap::new();
+
+    for (markdown, body_offset) in documents {
+        // Parser offsets are into the markdown with shortcodes cut out, or into a shortcode body
+        let original_range = |range: Range<usize>| match body_offset {
+            Some(offset) => offset + range.start..offset + range.end,
+            None => extracted.original_offset(range.start)..extracted.original_offset(range.end),
+        };
+        for (event, range) in Parser::new_ext(markdown, options).into_offset_iter() {
+            match &event {
+                Event::Text(t) | Event::Code(t) => block_text.push_str(t),
+                Event::SoftBreak | Event::HardBreak => block_text.push(' '),
+                Event::Start(Tag::Paragraph | Tag::Heading { .. } | Tag::Item | Tag::TableCell) => {
+                    // A nested block, such as a sublist, ends the text of the links before it
+                    take_snippets(&mut snippets, &mut block_links, &block_text);
+                    block_text.clear()
                 }
-                Tag::Link { dest_url, .. } => {
-                    if !is_internal_link(&dest_url) {
-                        continue;
+                Event::End(
+                    TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::Item | TagEnd::TableCell,
+                ) => take_snippets(&mut snippets, &mut block_links, &block_text),
+                _ => (),
+            }
+            match event {
+                Event::Start(tag) => match tag {
+                    Tag::Image { dest_url, .. } => {
+                        if !is_internal_link(&dest_url) || dest_url.starts_with("data:") {
+                            continue;
+                        }
+                        let dest = LinkDest::parse(&dest_url);
+                        let asset_path = match md_path.try_resolve_link(&dest.path, mounts) {
+                            Ok(asset_path) => asset_path,
+                            Err(e) => {
+                                diagnostics.push(
+                                    Diagnostic::error(
+                                        &md_path,
+                                        format!("Invalid image path: {}", e),
+                                    )
+                                    .with_span(source, original_range(range)),
+                                );
+                                continue;
+                            }
+                        };
+                        assets.push(asset_path.clone());
+                        asset_url_map.push((
+                            dest_url.to_string(),
+                            asset_path,
+                            original_range(range),
+                        ));
+                    }
+                    Tag::Link {
+                        link_type: LinkType::WikiLink { .. },
+                        dest_url,
+                        ..
+                    } => {
+                        block_links.push((dest_url.to_string(), block_text.len()));
+                        wikilinks.push((dest_url.to_string(), original_range(range)));
                     }
-                    let original_url = dest_url.to_string();
-                    let asset_path =
-                        SrcPath::from_relaxed_path(PathBuf::from(dest_url.as_ref()), anchor_path);
-                    assets.push(asset_path.clone());
-                    asset_url_map.push((original_url, asset_path));
+                    Tag::Link { dest_url, .. } => {
+                        if !is_internal_link(&dest_url) {
+                            continue;
+                        }
+                        let dest = LinkDest::parse(&dest_url);
+                        let target = match md_path.try_resolve_link(&dest.path, mounts) {
+                            Ok(target) => target,
+                            Err(e) => {
+                                diagnostics.push(
+                                    Diagnostic::error(&md_path, format!("Invalid link: {}", e))
+                                        .with_span(source, original_range(range)),
+                                );
+                                continue;
+                            }
+                        };
+                        if is_page_link(&dest.path) {
+                            block_links.push((dest_url.to_string(), block_text.len()));
+                            page_links.push((dest_url.to_string(), target, original_range(range)));
+                            continue;
+                        }
+                        // A link to a URL of the site rather than to a file, such as `/tags/rust/`
+                        // or `../blog/`, is written as is
+                        if is_site_url(&dest.path) && !config.vfs.is_file(&target) {
+                            continue;
+                        }
+                        assets.push(target.clone());
+                        asset_url_map.push((dest_url.to_string(), target, original_range(range)));
+                    }
+                    _ => (),
+                },
+                Event::Html(html) | Event::InlineHtml(html)
+                    if html.trim().starts_with("<!--aaska-shortcode-") => {}
+                Event::Html(_) | Event::InlineHtml(_) if !found_html => {
+                    found_html = true;
+                    diagnostics.push(
+                        Diagnostic::warning(
+                            &md_path,
+                            "HTML is passed through as is, links and assets in it are not tracked",
+                        )
+                        .with_span(source, original_range(range)),
+                    );
                 }
                 _ => (),
-            },
-            Event::Html(_html) | Event::InlineHtml(_html) => {
-                warn!(
-                    "HTML content found but skipped, any links or assets in HTML are not tracked."
+            }
+        }
+        take_snippets(&mut snippets, &mut block_links, &block_text);
+        block_text.clear();
+    }
+
+    let headings = page_headings(db, md_file).await?;
+
+    // Wikilinks resolve against the whole site, and may carry a heading
+    let mut wikilink_urls: Vec<(String, String)> = Vec::new();
+    for (dest, range) in wikilinks {
+        let (name, heading) = crate::wikilink::split(&dest);
+        // `[[#Heading]]` links to a heading of the page itself
+        let same_page = name.trim().is_empty() && heading.is_some();
+        let target = if same_page {
+            WikiTarget::Found(md_file)
+        } else {
+            crate::wikilink::resolve_wikilink(db, name.to_string()).await?
+        };
+        match target {
+            WikiTarget::Found(target) => {
+                let target_path = (*target.path(db)?).clone();
+                let mut url = if same_page {
+                    String::new()
+                } else {
+                    crate::site::page_url(&target_path)
+                };
+                if let Some(heading) = heading {
+                    let target_headings = page_headings(db, target).await?;
+                    let id = match crate::wikilink::find_heading(&target_headings, heading) {
+                        Some(found) => found.id.clone(),
+                        None => {
+                            let message = format!(
+                                "Wikilink [[{}]] points to a heading {} does not have",
+                                dest,
+                                target_path.rel_path()
+                            );
+                            diagnostics.push(
+                                Diagnostic::warning(&md_path, message)
+                                    .with_span(source, range.clone()),
+                            );
+                            crate::collections::slugify(heading)
+                        }
+                    };
+                    url.push('#');
+                    url.push_str(&id);
+                }
+                wikilink_urls.push((dest.clone(), url));
+                if !same_page {
+                    page_links.push((dest, target_path, range));
+                }
+            }
+            WikiTarget::Ambiguous(candidates) => {
+                let message = format!(
+                    "Ambiguous wikilink [[{}]], it could be any of: {}",
+                    dest,
+                    candidates
+                        .iter()
+                        .map(|c| c.rel_path())
+                        .collect::<Vec<_>>()
+                        .join(", ")
                 );
+                diagnostics.push(Diagnostic::error(&md_path, message).with_span(source, range));
+            }
+            WikiTarget::Missing => {
+                let message = format!("Unresolved wikilink [[{}]]", dest);
+                diagnostics.push(Diagnostic::error(&md_path, message).with_span(source, range));
             }
-            _ => (),
         }
     }
 
     // Load all SourceFiles first (outside async closures) to avoid picante cycles
-    let mut asset_files: Vec<(String, SrcPath, SourceFile)> = Vec::new();
-    for (original_url, asset_path) in asset_url_map.iter() {
+    let mut asset_files: Vec<(String, SrcPath, Range<usize>, SourceFile)> = Vec::new();
+    for (original_url, asset_path, range) in asset_url_map {
         match SourceFile::from_disk(db, asset_path.clone()) {
             Ok(file) => {
-                asset_files.push((original_url.clone(), asset_path.clone(), file));
+                asset_files.push((original_url, asset_path, range, file));
             }
             Err(e) => {
-                error!("Failed to load asset {}: {:?}", asset_path.display(), e);
+                let message = format!("Failed to load asset {}: {:#}", asset_path.display(), e);
+                diagnostics.push(Diagnostic::error(&md_path, message).with_span(source, range));
             }
         }
     }
@@ -157,12 +474,18 @@ pub async fn render_chonk<DB: Db>(db: &DB, md_file: SourceFile) -> PicanteResult
 
     let mut asset_futures = asset_files
         .into_iter()
-        .map(|(original_url, asset_path, file)| async move {
+        .map(|(original_url, asset_path, range, file)| async move {
             use std::time::Instant;
             let query_start = Instant::now();
 
-            let result = match process_asset(db, file).await {
-                Ok(processed) => {
+            let processed = process_asset(db, file).await;
+            let size = if crate::image::is_image(&asset_path) {
+                crate::image::image_size(db, file).await
+            } else {
+                Ok(None)
+            };
+            let result = match processed.and_then(|processed| Ok((processed, size?))) {
+                Ok((processed, size)) => {
                     let query_duration = query_start.elapsed();
                     info!(
                         "Processed asset {} -> {} in {:?}",
@@ -170,12 +493,18 @@ pub async fn render_chonk<DB: Db>(db: &DB, md_file: SourceFile) -> PicanteResult
                         processed.hashed_name,
                         query_duration
                     );
-                    Some((original_url, processed.hashed_name, query_duration))
-                }
-                Err(e) => {
-                    error!("Failed to process asset {}: {:?}", asset_path.display(), e);
-                    None
+                    Ok((
+                        original_url,
+                        asset_path,
+                        processed.hashed_name,
+                        size,
+                        query_duration,
+                    ))
                 }
+                Err(e) => Err((
+                    range,
+                    format!("Failed to process asset {}: {:?}", asset_path.display(), e),
+                )),
             };
             result
         })
@@ -189,12 +518,32 @@ pub async fn render_chonk<DB: Db>(db: &DB, md_file: SourceFile) -> PicanteResult
     let parallel_total = parallel_start.elapsed();
 
     let mut asset_map = HashMap::new();
+    let mut image_sizes = HashMap::new();
+    let mut hashed_assets = Vec::new();
     let mut query_times = Vec::new();
-    for result in results.into_iter().flatten() {
-        let (original, hashed, duration) = result;
-        asset_map.insert(original, hashed);
+    for result in results {
+        let (original, asset_path, hashed, size, duration) = match result {
+            Ok(processed) => processed,
+            Err((range, message)) => {
+                diagnostics.push(Diagnostic::error(&md_path, message).with_span(source, range));
+                continue;
+            }
+        };
+        asset_map.insert(original.clone(), LinkDest::parse(&original).to_url(&hashed));
+        if let Some(size) = size {
+            image_sizes.insert(original.clone(), size);
+        }
+        // The same file may be linked with different queries or fragments
+        if !hashed_assets.iter().any(|(path, _)| *path == asset_path) {
+            hashed_assets.push((asset_path, hashed));
+        }
         query_times.push(duration);
     }
+    for (original, target, _) in page_links.iter() {
+        let url = LinkDest::parse(original).to_url(&crate::site::page_url(target));
+        asset_map.insert(original.clone(), url);
+    }
+    asset_map.extend(wikilink_urls);
 
     // Log average query time and actual parallel execution time
     if !query_times.is_empty() {
@@ -208,26 +557,244 @@ pub async fn render_chonk<DB: Db>(db: &DB, md_file: SourceFile) -> PicanteResult
     }
 
     // Second pass: generate HTML with URL resolver
-    let parser2 = Parser::new_ext(file_contents_str, options);
+    // Headings without an explicit id get the one computed in the first pass, shortcode bodies
+    // taking theirs after the page's
+    let mut heading_ids = headings
+        .iter()
+        .map(|h| h.id.clone())
+        .collect::<Vec<_>>()
+        .into_iter();
+    let parser2 = with_heading_ids(
+        Parser::new_ext(file_contents_str, options),
+        &mut heading_ids,
+    );
     let mut html = String::new();
-    crate::html::push_html_with_resolver(&mut html, parser2, |url: &str| {
+    let resolve = |url: &str| {
         asset_map
             .get(url)
             .cloned()
             .unwrap_or_else(|| url.to_string())
-    });
+    };
+    let html_options = crate::html::HtmlOptions {
+        images: Some(image_sizes),
+        external_links: config.site.external_links.clone(),
+        base_url: config.site.base_url.clone(),
+        alerts: config.site.alerts.clone(),
+        renderer: config.renderer.clone(),
+    };
+    crate::html::push_html_with_options(&mut html, parser2, resolve, html_options.clone());
+
+    // Only pages calling shortcodes depend on the set of templates
+    let site_templates = if shortcodes.is_empty() {
+        Vec::new()
+    } else {
+        crate::shortcode::ShortcodeTemplates::templates(db)?.unwrap_or_default()
+    };
+    let mut shortcode_templates = Vec::new();
+    for (i, call) in shortcodes.iter().enumerate() {
+        let mut body_html = String::new();
+        if let Some(body) = &call.body {
+            let parser = with_heading_ids(Parser::new_ext(body, options), &mut heading_ids);
+            crate::html::push_html_with_options(
+                &mut body_html,
+                parser,
+                resolve,
+                html_options.clone(),
+            );
+        }
+        let expanded = match expand_shortcode(
+            db,
+            call,
+            &body_html,
+            &site_templates,
+            &mut shortcode_templates,
+        ) {
+            Ok(expanded) => expanded,
+            Err(e) => {
+                let message = format!("Failed to expand shortcode `{}`: {:#}", call.name, e);
+                diagnostics.push(
+                    Diagnostic::error(&md_path, message).with_span(source, call.range.clone()),
+                );
+                String::new()
+            }
+        };
+        html = html.replace(&ShortcodeCall::placeholder(i), &expanded);
+    }
 
     Ok(Chonk {
         html,
         assets,
+        hashed_assets,
+        page_links: page_links
+            .into_iter()
+            .map(|(dest, target, range)| PageLink {
+                snippet: snippets.get(&dest).cloned().unwrap_or_default(),
+                span: Span::new(source, range),
+                target,
+            })
+            .collect(),
+        shortcode_templates,
+        headings,
+        diagnostics,
         og_srcpath: (*md_file.path(db)?).clone(),
     })
 }
 
+/// Cuts the snippet of each pending link out of the text of the block they are in.
+fn take_snippets(
+    snippets: &mut std::collections::HashMap<String, String>,
+    block_links: &mut Vec<(String, usize)>,
+    block_text: &str,
+) {
+    for (dest, at) in block_links.drain(..) {
+        snippets
+            .entry(dest)
+            .or_insert_with(|| crate::backlinks::snippet(block_text, at));
+    }
+}
+
+/// Gives the headings without an explicit id the next of `ids`.
+fn with_heading_ids<'a>(
+    parser: Parser<'a>,
+    ids: &mut std::vec::IntoIter<String>,
+) -> impl Iterator<Item = Event<'a>> {
+    parser.map(move |event| match event {
+        Event::Start(Tag::Heading {
+            level,
+            id,
+            classes,
+            attrs,
+        }) => {
+            let computed = ids.next();
+            Event::Start(Tag::Heading {
+                level,
+                id: id.or(computed.map(CowStr::from)),
+                classes,
+                attrs,
+            })
+        }
+        event => event,
+    })
+}
+
+/// Expands a shortcode, preferring a template from the site (see
+/// [`crate::shortcode::ShortcodeTemplates`]) over a registered implementation. Templates, and the
+/// files they include, are read through the db so the page depends on them. `body_html` is the
+/// body of the call, rendered like the rest of the page.
+fn expand_shortcode<DB: Db>(
+    db: &DB,
+    call: &ShortcodeCall,
+    body_html: &str,
+    site_templates: &[(String, SourceFile)],
+    templates: &mut Vec<SrcPath>,
+) -> Result<String> {
+    let config = crate::config(db);
+    let Some((_, template)) = site_templates.iter().find(|(name, _)| *name == call.name) else {
+        return match config.shortcodes.get(&call.name) {
+            Some(shortcode) => shortcode.render(call),
+            None => Err(eyre!("Unknown shortcode `{}`", call.name)),
+        };
+    };
+
+    let template_src = (*template.path(db)?).clone();
+    let contents = template.contents(db)?;
+    templates.push(template_src.clone());
+
+    crate::shortcode::render_template(std::str::from_utf8(&contents)?, call, body_html, |file| {
+        let included = template_src.try_sibling(file)?;
+        let contents = SourceFile::from_disk(db, included.clone())?.contents(db)?;
+        templates.push(included);
+        Ok(String::from_utf8_lossy(&contents).into_owned())
+    })
+}
+
+/// Headings of a page, shortcode bodies included, with the ids they are rendered with. Kept apart
+/// from [`render_chonk`] so that wikilinks can check the headings of the pages they point to
+/// without depending on how those pages render.
+#[picante::tracked]
+pub async fn page_headings<DB: Db>(db: &DB, md_file: SourceFile) -> PicanteResult<Vec<Heading>> {
+    let _timing = crate::timings::query(db, "page_headings", md_file);
+    let Ok(source) = source_text(db, md_file).await? else {
+        return Ok(Vec::new());
+    };
+    let extracted = crate::shortcode::extract(&source);
+    // In the order `render_chonk` renders them
+    let documents = std::iter::once(extracted.markdown.as_str()).chain(
+        extracted
+            .calls
+            .iter()
+            .filter_map(|call| call.body.as_deref()),
+    );
+
+    let mut headings: Vec<Heading> = Vec::new();
+    let mut current: Option<(HeadingLevel, Option<String>, String)> = None;
+    for markdown in documents {
+        for event in Parser::new_ext(markdown, crate::MD_OPTIONS) {
+            match event {
+                Event::Start(Tag::Heading { level, id, .. }) => {
+                    current = Some((level, id.map(|id| id.to_string()), String::new()));
+                }
+                Event::Text(t) | Event::Code(t) => {
+                    if let Some((_, _, text)) = current.as_mut() {
+                        text.push_str(&t);
+                    }
+                }
+                Event::End(TagEnd::Heading(_)) => {
+                    if let Some((level, id, text)) = current.take() {
+                        let id = id.unwrap_or_else(|| {
+                            unique_heading_id(&crate::collections::slugify(&text), &headings)
+                        });
+                        headings.push(Heading {
+                            level: level as u8,
+                            id,
+                            text,
+                        });
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    Ok(headings)
+}
+
+/// Makes a heading id unique within the page by appending a counter, as in `intro`, `intro-1`.
+fn unique_heading_id(slug: &str, headings: &[Heading]) -> String {
+    let taken = |id: &str| headings.iter().any(|h| h.id == id);
+    if !taken(slug) {
+        return slug.to_string();
+    }
+    (1..)
+        .map(|i| format!("{}-{}", slug, i))
+        .find(|id| !taken(id))
+        .unwrap()
+}
+
 /// Checks whether a link is an internal link (from our website) or an external link.
 /// If it's an internal link, it is a depencency
+///
+/// Root-relative links (`/images/a.png`) are internal, see [`SrcPath::try_resolve_link`].
 fn is_internal_link(link: &str) -> bool {
-    todo!()
+    !(link.is_empty()
+        || link.starts_with('#')
+        || link.starts_with('?')
+        || link.starts_with("//")
+        || link.contains("://")
+        || link.starts_with("mailto:")
+        || link.starts_with("tel:"))
+}
+
+/// Checks whether an internal link points to another page rather than to an asset.
+fn is_page_link(link: &str) -> bool {
+    link.ends_with(".md")
+}
+
+/// Checks whether an internal link may be a URL generated by the site, such as a listing: it is
+/// root-relative, or names a directory rather than a file with an extension. Links to missing
+/// files with an extension are missing assets instead.
+fn is_site_url(link: &str) -> bool {
+    let name = link.rsplit('/').next().unwrap_or_default();
+    link.starts_with('/') || matches!(name, "" | "." | "..") || !name.contains('.')
 }
 
 #[picante::tracked]
@@ -237,6 +804,7 @@ pub async fn process_md<DB: Db>(db: &DB, input: SourceFile) -> PicanteResult<Par
 
 #[picante::tracked]
 pub async fn process_asset<DB: Db>(db: &DB, input: SourceFile) -> PicanteResult<ProcessedAsset> {
+    let _timing = crate::timings::query(db, "process_asset", input);
     use sha2::{Digest, Sha256};
 
     let path = input.path(db)?;
@@ -247,17 +815,14 @@ pub async fn process_asset<DB: Db>(db: &DB, input: SourceFile) -> PicanteResult<
         .to_string_lossy()
         .to_string();
 
-    // Artificial delay to make parallelism visible (remove this later)
-    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-
-    // Generate hash of asset contents
-    let mut hasher = Sha256::new();
-    hasher.update(contents);
-    let hash = hasher.finalize();
-    let hash_str = hash
-        .iter()
-        .map(|b| format!("{:02x}", b))
-        .collect::<String>();
+    // Generate hash of asset contents, off the async workers as assets can be large
+    let hash_str = crate::cpu::run(move || {
+        Sha256::digest(&contents)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    })
+    .await;
     let short_hash = &hash_str[..8]; // Use first 8 chars
 
     // Create hashed filename: name.hash.ext
@@ -0,0 +1,236 @@
+//! Errors and warnings found while building, pointing at the source they come from.
+//!
+//! Queries return diagnostics as part of their output rather than logging them, so a page that is
+//! served from the cache reports the same problems as when it was first rendered.
+
+use std::fmt::{self, Write};
+use std::ops::Range;
+
+use crate::backlinks::page_links;
+use crate::db::{Db, SourceFile, render_chonk, source_text};
+use crate::frontmatter::FrontMatter;
+use crate::path::SrcPath;
+use crate::site::{excluded_pages, is_published, url_conflicts};
+use picante::PicanteResult;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => f.write_str("warning"),
+            Severity::Error => f.write_str("error"),
+        }
+    }
+}
+
+/// A byte range in a source file, with the 1-based line and column (in characters) of its start.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(source: &str, range: Range<usize>) -> Self {
+        let start = floor_char_boundary(source, range.start);
+        let end = floor_char_boundary(source, range.end.max(start));
+        Self::after(&source[..start], start..end)
+    }
+
+    /// A span starting right after `prefix`, for sources that cannot be sliced at `range`.
+    pub fn after(prefix: &str, range: Range<usize>) -> Self {
+        let line_start = prefix.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        Self {
+            start: range.start,
+            end: range.end,
+            line: prefix.matches('\n').count() + 1,
+            column: prefix[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+fn floor_char_boundary(s: &str, i: usize) -> usize {
+    let mut i = i.min(s.len());
+    while !s.is_char_boundary(i) {
+        i -= 1;
+    }
+    i
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: SrcPath,
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn error(file: &SrcPath, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            file: file.clone(),
+            span: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn warning(file: &SrcPath, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(file, message)
+        }
+    }
+
+    pub fn with_span(mut self, source: &str, range: Range<usize>) -> Self {
+        self.span = Some(Span::new(source, range));
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Formats the diagnostic the way rustc does, quoting the offending line of `source`.
+    pub fn render(&self, source: Option<&str>) -> String {
+        let mut out = format!("{}: {}\n", self.severity, self.message);
+        let Some(span) = &self.span else {
+            let _ = writeln!(out, " --> {}", self.file.display());
+            return out;
+        };
+
+        let gutter = span.line.to_string().len();
+        let _ = writeln!(
+            out,
+            "{:gutter$}--> {}:{}:{}",
+            "",
+            self.file.display(),
+            span.line,
+            span.column
+        );
+        let Some(source) = source else {
+            return out;
+        };
+        if let Some(line) = source.lines().nth(span.line - 1) {
+            let width = source
+                .get(span.start..span.end)
+                .unwrap_or_default()
+                .chars()
+                .take_while(|c| *c != '\n')
+                .count()
+                .max(1);
+            let _ = writeln!(out, "{:gutter$} |", "");
+            let _ = writeln!(out, "{} | {}", span.line, line.trim_end());
+            let _ = writeln!(
+                out,
+                "{:gutter$} | {}{}",
+                "",
+                " ".repeat(span.column - 1),
+                "^".repeat(width)
+            );
+        }
+        out
+    }
+}
+
+/// Everything wrong with a page: its encoding, its front matter, its content, and links to pages
+/// that are not part of the build.
+#[picante::tracked]
+pub async fn page_diagnostics<DB: Db>(
+    db: &DB,
+    md_file: SourceFile,
+) -> PicanteResult<Vec<Diagnostic>> {
+    let _timing = crate::timings::query(db, "page_diagnostics", md_file);
+    let src = md_file.path(db)?;
+    let source = match source_text(db, md_file).await? {
+        Ok(source) => source,
+        Err(e) => {
+            let mut diagnostic = Diagnostic::error(&src, e.message);
+            diagnostic.span = Some(e.span);
+            return Ok(vec![diagnostic]);
+        }
+    };
+    // Drafts and scheduled pages are not checked until they are published
+    if !is_published(db, md_file).await? {
+        return Ok(Vec::new());
+    }
+
+    let mut diagnostics = Vec::new();
+    if let Err(e) = FrontMatter::from_markdown(&source) {
+        diagnostics.push(Diagnostic::error(
+            &src,
+            format!("Invalid front-matter: {:#}", e),
+        ));
+    }
+
+    diagnostics.extend(render_chonk(db, md_file).await?.diagnostics);
+
+    for (url, pages) in url_conflicts(db).await? {
+        if !pages.contains(&*src) {
+            continue;
+        }
+        let others: Vec<_> = pages
+            .iter()
+            .filter(|page| *page != &*src)
+            .map(|page| page.rel_path())
+            .collect();
+        diagnostics.push(Diagnostic::error(
+            &src,
+            format!(
+                "Page is published at {} like {}, only one of them can be written there",
+                url,
+                others.join(", ")
+            ),
+        ));
+    }
+
+    let excluded = excluded_pages(db).await?;
+    for link in page_links(db, md_file).await? {
+        if excluded.contains(&link.target) {
+            let mut diagnostic = Diagnostic::error(
+                &src,
+                format!(
+                    "Link to {}, which is left out of the build",
+                    link.target.rel_path()
+                ),
+            );
+            diagnostic.span = Some(link.span);
+            diagnostics.push(diagnostic);
+        }
+    }
+    Ok(diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let source = "---\ntitle: Hi\n---\nSee [[Nope]] for more.\n";
+        let file = SrcPath::from_relaxed_path("post.md", "/site/content");
+        let start = source.find("[[").unwrap();
+        let diagnostic = Diagnostic::error(&file, "Unresolved wikilink [[Nope]]")
+            .with_span(source, start..start + "[[Nope]]".len());
+
+        let span = diagnostic.span.clone().unwrap();
+        assert_eq!((span.line, span.column), (4, 5));
+        assert_eq!(
+            diagnostic.render(Some(source)),
+            "error: Unresolved wikilink [[Nope]]\n \
+             --> /site/content/post.md:4:5\n  \
+             |\n\
+             4 | See [[Nope]] for more.\n  \
+             |     ^^^^^^^^\n"
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Decoding markdown sources. Files are expected to be UTF-8, a leading byte order mark is
+//! stripped, and UTF-16 files (which always start with one) are transcoded.
+
+use std::borrow::Cow;
+
+use crate::diagnostics::Span;
+
+/// Where and why a file could not be decoded.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct DecodeError {
+    pub message: String,
+    /// The first invalid bytes. Line and column are counted in the text decoded before them.
+    pub span: Span,
+}
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const UTF16_LE_BOM: &[u8] = b"\xFF\xFE";
+const UTF16_BE_BOM: &[u8] = b"\xFE\xFF";
+
+pub fn decode(bytes: &[u8]) -> Result<Cow<'_, str>, DecodeError> {
+    if let Some(utf16) = bytes.strip_prefix(UTF16_LE_BOM) {
+        return decode_utf16(utf16, u16::from_le_bytes).map(Cow::Owned);
+    }
+    if let Some(utf16) = bytes.strip_prefix(UTF16_BE_BOM) {
+        return decode_utf16(utf16, u16::from_be_bytes).map(Cow::Owned);
+    }
+
+    let (bom, utf8) = match bytes.strip_prefix(UTF8_BOM) {
+        Some(utf8) => (UTF8_BOM.len(), utf8),
+        None => (0, bytes),
+    };
+    std::str::from_utf8(utf8).map(Cow::Borrowed).map_err(|e| {
+        let valid = std::str::from_utf8(&utf8[..e.valid_up_to()]).unwrap_or_default();
+        let start = bom + e.valid_up_to();
+        let end = start + e.error_len().unwrap_or(utf8.len() - e.valid_up_to());
+        DecodeError {
+            message: if looks_binary(bytes) {
+                "File is not text, it contains binary data".to_string()
+            } else {
+                format!("File is not valid UTF-8, invalid byte at offset {}", start)
+            },
+            span: Span::after(valid, start..end),
+        }
+    })
+}
+
+/// Decodes UTF-16 after its byte order mark.
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> Result<String, DecodeError> {
+    let bom = UTF16_LE_BOM.len();
+    let mut text = String::with_capacity(bytes.len() / 2);
+    let units = bytes.chunks_exact(2).map(|pair| unit([pair[0], pair[1]]));
+    let mut offset = bom;
+    for c in char::decode_utf16(units) {
+        match c {
+            Ok(c) => {
+                text.push(c);
+                offset += 2 * c.len_utf16();
+            }
+            Err(_) => {
+                return Err(DecodeError {
+                    message: format!(
+                        "File is not valid UTF-16, unpaired surrogate at offset {}",
+                        offset
+                    ),
+                    span: Span::after(&text, offset..offset + 2),
+                });
+            }
+        }
+    }
+    if bytes.len() % 2 == 1 {
+        let offset = bom + bytes.len() - 1;
+        return Err(DecodeError {
+            message: "File is not valid UTF-16, it has an odd number of bytes".to_string(),
+            span: Span::after(&text, offset..offset + 1),
+        });
+    }
+    Ok(text)
+}
+
+/// Text files do not contain NUL bytes, unless they are UTF-16, which is handled before.
+fn looks_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(8000).any(|b| *b == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(b"# Hi").unwrap(), "# Hi");
+        assert_eq!(decode(b"\xEF\xBB\xBF# Hi").unwrap(), "# Hi");
+        assert_eq!(decode(b"\xFF\xFE#\0 \0H\0i\0").unwrap(), "# Hi");
+        assert_eq!(decode(b"\xFE\xFF\0#\0 \0H\0i").unwrap(), "# Hi");
+
+        let err = decode(b"# Hi\nca\xE9\n").unwrap_err();
+        assert_eq!((err.span.start, err.span.end), (7, 8));
+        assert_eq!((err.span.line, err.span.column), (2, 3));
+
+        let err = decode(b"\x89PNG\r\n\x1a\n\0\0").unwrap_err();
+        assert!(err.message.contains("binary"));
+
+        let err = decode(b"\xFF\xFE#\0\0\xD8").unwrap_err();
+        assert_eq!(err.span.start, 4);
+    }
+}
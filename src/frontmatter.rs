@@ -0,0 +1,262 @@
+//! Page front-matter, the YAML metadata block at the top of a markdown file.
+
+use crate::internal_prelude::*;
+use std::collections::BTreeMap;
+
+use pulldown_cmark::{Event, MetadataBlockKind, Parser, Tag, TagEnd};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+    pub date: Option<Date>,
+    /// Last time the page was meaningfully edited, preferred over `date` for `lastmod`
+    pub updated: Option<Date>,
+    pub draft: bool,
+    /// Whether the page is listed in `sitemap.xml`
+    pub sitemap: bool,
+    /// Number of pages per listing page, for section index pages (`_index.md`)
+    pub paginate_by: Option<usize>,
+    /// Any other key, such as taxonomy terms (`tags: [rust, web]`)
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
+}
+
+impl Default for FrontMatter {
+    fn default() -> Self {
+        Self {
+            title: None,
+            date: None,
+            updated: None,
+            draft: false,
+            sitemap: true,
+            paginate_by: None,
+            extra: BTreeMap::new(),
+        }
+    }
+}
+
+impl FrontMatter {
+    /// Extracts the front-matter from a markdown document. Documents without a metadata block get
+    /// the default front-matter.
+    pub fn from_markdown(md: &str) -> eyre::Result<Self> {
+        let mut parser = Parser::new_ext(md, crate::MD_OPTIONS);
+        // The metadata block can only be the very first element of the document
+        match parser.next() {
+            Some(Event::Start(Tag::MetadataBlock(MetadataBlockKind::YamlStyle))) => (),
+            Some(Event::Start(Tag::MetadataBlock(MetadataBlockKind::PlusesStyle))) => {
+                warn!("TOML front-matter is not supported, ignoring it");
+                return Ok(Self::default());
+            }
+            _ => return Ok(Self::default()),
+        }
+
+        let mut yaml = String::new();
+        for event in parser {
+            match event {
+                Event::Text(text) => yaml.push_str(&text),
+                Event::End(TagEnd::MetadataBlock(_)) => break,
+                _ => (),
+            }
+        }
+        Self::from_yaml(&yaml)
+    }
+
+    /// Returns the terms the page is classified under for a taxonomy. Both a list and a single
+    /// string are accepted.
+    pub fn terms(&self, taxonomy: &str) -> Vec<String> {
+        match self.extra.get(taxonomy) {
+            Some(serde_yaml::Value::Sequence(terms)) => terms
+                .iter()
+                .filter_map(|term| term.as_str().map(str::to_string))
+                .collect(),
+            Some(serde_yaml::Value::String(term)) => vec![term.clone()],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn from_yaml(yaml: &str) -> eyre::Result<Self> {
+        if yaml.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        Ok(serde_yaml::from_str(yaml)?)
+    }
+}
+
+/// A point in time as written in front-matter, either a plain date (`2024-01-15`) or a date and
+/// time (`2024-01-15T10:30:00Z`). Stored as seconds since the unix epoch, in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    secs: i64,
+    has_time: bool,
+}
+
+impl Date {
+    pub fn from_unix(secs: i64) -> Self {
+        Self {
+            secs,
+            has_time: true,
+        }
+    }
+
+    pub fn as_unix(&self) -> i64 {
+        self.secs
+    }
+
+    /// Parses `YYYY-MM-DD`, optionally followed by `THH:MM[:SS]` and a `Z` or `±HH:MM` offset.
+    /// A space is accepted in place of the `T`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (date, time) = match s.find(['T', ' ']) {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+
+        let mut parts = date.splitn(3, '-');
+        let year: i64 = parts.next()?.parse().ok()?;
+        let month: u32 = parts.next()?.parse().ok()?;
+        let day: u32 = parts.next()?.parse().ok()?;
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+        let days = days_from_civil(year, month, day);
+
+        let Some(time) = time else {
+            return Some(Self {
+                secs: days * 86400,
+                has_time: false,
+            });
+        };
+
+        let (clock, offset) = if let Some(clock) = time.strip_suffix(['Z', 'z']) {
+            (clock, 0)
+        } else if let Some(i) = time.rfind(['+', '-']) {
+            let sign = if time.as_bytes()[i] == b'-' { -1 } else { 1 };
+            let (h, m) = time[i + 1..].split_once(':')?;
+            let offset = h.parse::<i64>().ok()? * 3600 + m.parse::<i64>().ok()? * 60;
+            (&time[..i], sign * offset)
+        } else {
+            (time, 0)
+        };
+
+        let mut clock = clock.split(':');
+        let hour: i64 = clock.next()?.parse().ok()?;
+        let minute: i64 = clock.next()?.parse().ok()?;
+        // Fractional seconds are accepted but dropped
+        let second: i64 = match clock.next() {
+            Some(sec) => sec.split('.').next()?.parse().ok()?,
+            None => 0,
+        };
+        if hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+
+        Some(Self {
+            secs: days * 86400 + hour * 3600 + minute * 60 + second - offset,
+            has_time: true,
+        })
+    }
+
+    /// Formats the date in the W3C datetime format used by sitemaps and feeds. Dates without a time
+    /// component are written as a plain date.
+    pub fn to_w3c(&self) -> String {
+        let days = self.secs.div_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        if !self.has_time {
+            return format!("{:04}-{:02}-{:02}", year, month, day);
+        }
+        let secs = self.secs.rem_euclid(86400);
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs % 3600 / 60,
+            secs % 60
+        )
+    }
+}
+
+impl std::fmt::Display for Date {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_w3c())
+    }
+}
+
+impl serde::Serialize for Date {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_w3c())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Date {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Date::parse(&s).ok_or_else(|| serde::de::Error::custom(format!("invalid date `{}`", s)))
+    }
+}
+
+// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date_roundtrip() {
+        let cases = vec![
+            ("2024-01-15", "2024-01-15"),
+            ("1970-01-01T00:00:00Z", "1970-01-01T00:00:00Z"),
+            ("2024-02-29T10:30:00Z", "2024-02-29T10:30:00Z"),
+            ("2024-03-01 10:30", "2024-03-01T10:30:00Z"),
+            ("2024-03-01T10:30:00+02:00", "2024-03-01T08:30:00Z"),
+            ("1999-12-31T23:00:00-02:00", "2000-01-01T01:00:00Z"),
+        ];
+        for (input, expected) in cases {
+            let date = Date::parse(input).unwrap();
+            assert_eq!(date.to_w3c(), expected);
+        }
+        assert_eq!(Date::parse("1970-01-02").unwrap().as_unix(), 86400);
+        assert!(Date::parse("2024-13-01").is_none());
+        assert!(Date::parse("yesterday").is_none());
+    }
+
+    #[test]
+    fn test_front_matter_yaml() {
+        let fm =
+            FrontMatter::from_yaml("title: Hello\ndate: 2024-01-15\nsitemap: false\n").unwrap();
+        assert_eq!(fm.title.as_deref(), Some("Hello"));
+        assert_eq!(fm.date, Date::parse("2024-01-15"));
+        assert!(!fm.sitemap);
+        assert!(!fm.draft);
+        assert!(fm.terms("tags").is_empty());
+        let fm = FrontMatter::from_yaml("tags: [rust, web]\ncategories: notes\n").unwrap();
+        assert_eq!(fm.terms("tags"), vec!["rust", "web"]);
+        assert_eq!(fm.terms("categories"), vec!["notes"]);
+        assert_eq!(FrontMatter::from_yaml("").unwrap(), FrontMatter::default());
+    }
+}
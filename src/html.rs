@@ -21,15 +21,423 @@
 
 //! HTML renderer that takes an iterator of events as input.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
 
 use pulldown_cmark::CowStr;
+
+use crate::config::{AlertsConfig, ExternalLinksConfig};
+use crate::image::ImageSize;
 use pulldown_cmark::Event::*;
-use pulldown_cmark::{Alignment, BlockQuoteKind, CodeBlockKind, Event, LinkType, Tag, TagEnd};
+use pulldown_cmark::{
+    Alignment, BlockQuoteKind, CodeBlockKind, Event, HeadingLevel, LinkType, Tag, TagEnd,
+};
 use pulldown_cmark_escape::{
     FmtWriter, IoWriter, StrWrite, escape_href, escape_html, escape_html_body_text,
 };
 
+/// What the site adds to the plain HTML output of markdown.
+#[derive(Clone)]
+pub struct HtmlOptions {
+    /// Sizes of local images by their URL in the markdown. When set, images are also marked to
+    /// be loaded lazily.
+    pub images: Option<HashMap<String, ImageSize>>,
+    pub external_links: ExternalLinksConfig,
+    /// URL the site is served from, telling external links apart
+    pub base_url: String,
+    pub alerts: AlertsConfig,
+    pub renderer: Arc<dyn HtmlRenderer>,
+}
+
+impl Default for HtmlOptions {
+    fn default() -> Self {
+        Self {
+            images: None,
+            external_links: ExternalLinksConfig::default(),
+            base_url: String::new(),
+            alerts: AlertsConfig::default(),
+            renderer: Arc::new(DefaultRenderer),
+        }
+    }
+}
+
+/// HTML written by the hooks of an [`HtmlRenderer`].
+pub struct HtmlOut {
+    html: String,
+    end_newline: bool,
+}
+
+impl HtmlOut {
+    /// Writes raw HTML.
+    pub fn write(&mut self, html: &str) {
+        self.html.push_str(html);
+        if !html.is_empty() {
+            self.end_newline = html.ends_with('\n');
+        }
+    }
+
+    /// Writes text, escaped for use in an element or attribute value.
+    pub fn text(&mut self, text: &str) {
+        // Writing to a string does not fail
+        let _ = escape_html(&mut self.html, text);
+        if !text.is_empty() {
+            self.end_newline = text.ends_with('\n');
+        }
+    }
+
+    /// Writes a URL, escaped for use in `href` or `src`.
+    pub fn href(&mut self, url: &str) {
+        let _ = escape_href(&mut self.html, url);
+        if !url.is_empty() {
+            self.end_newline = url.ends_with('\n');
+        }
+    }
+
+    /// Starts a new line unless at the start of one already, as block elements do.
+    pub fn block(&mut self) {
+        if !self.end_newline {
+            self.write("\n");
+        }
+    }
+}
+
+/// A table cell, as seen by [`HtmlRenderer::start_table_cell`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TableCell {
+    /// In the header row
+    pub head: bool,
+    pub alignment: Alignment,
+    /// Column of the cell
+    pub index: usize,
+}
+
+/// A link, as seen by [`HtmlRenderer::start_link`].
+#[derive(Debug, Clone, Copy)]
+pub struct Link<'a> {
+    pub link_type: LinkType,
+    /// Destination, once resolved. Email autolinks are given a `mailto:` URL.
+    pub url: &'a str,
+    pub title: &'a str,
+    /// Attributes to add, if the link leaves the site
+    pub external: Option<&'a ExternalLinksConfig>,
+}
+
+/// An image, as seen by [`HtmlRenderer::image`].
+#[derive(Debug, Clone, Copy)]
+pub struct Image<'a> {
+    pub link_type: LinkType,
+    /// Source, once resolved
+    pub url: &'a str,
+    /// Plain text of the image description
+    pub alt: &'a str,
+    pub title: &'a str,
+    /// Intrinsic size, for images of the site
+    pub size: Option<ImageSize>,
+    /// Load the image lazily
+    pub lazy: bool,
+}
+
+/// A GitHub-style alert, as seen by [`HtmlRenderer::start_alert`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    /// Name of the kind of alert, in lower case, such as `note`
+    pub kind: String,
+    pub title: String,
+    /// HTML of the icon, if icons are enabled
+    pub icon: Option<String>,
+}
+
+/// The kinds of alerts GitHub supports, with their default title and icon.
+const ALERTS: [(&str, &str, &str); 5] = [
+    ("note", "Note", "\u{2139}\u{FE0F}"),
+    ("tip", "Tip", "\u{1F4A1}"),
+    ("important", "Important", "\u{2757}"),
+    ("warning", "Warning", "\u{26A0}\u{FE0F}"),
+    ("caution", "Caution", "\u{1F6D1}"),
+];
+
+impl Alert {
+    /// The alert of a kind, if it is one GitHub supports or one the site adds.
+    fn new(config: &AlertsConfig, kind: &str) -> Option<Self> {
+        // Lowercased like the configured kinds, see `SiteConfig::load_from`
+        let kind = kind.to_lowercase();
+        let builtin = ALERTS.iter().find(|(name, ..)| *name == kind);
+        let configured = config.kinds.get(&kind);
+        if builtin.is_none() && configured.is_none() {
+            return None;
+        }
+        let title = configured
+            .and_then(|c| c.title.clone())
+            .or_else(|| builtin.map(|(_, title, _)| title.to_string()))
+            .unwrap_or_else(|| {
+                let mut chars = kind.chars();
+                chars
+                    .next()
+                    .map(|first| first.to_uppercase().chain(chars).collect())
+                    .unwrap_or_default()
+            });
+        let icon = configured
+            .and_then(|c| c.icon.clone())
+            .or_else(|| builtin.map(|(_, _, icon)| icon.to_string()))
+            .filter(|_| config.icons);
+        Some(Self { kind, title, icon })
+    }
+}
+
+fn alert_kind(kind: BlockQuoteKind) -> &'static str {
+    match kind {
+        BlockQuoteKind::Note => "note",
+        BlockQuoteKind::Tip => "tip",
+        BlockQuoteKind::Important => "important",
+        BlockQuoteKind::Warning => "warning",
+        BlockQuoteKind::Caution => "caution",
+    }
+}
+
+/// The markup written for each element. Every hook defaults to the markup aaska writes, so an
+/// implementation only overrides the elements it customizes, such as wrapping tables:
+///
+/// ```
+/// # use aaska2::html::{DefaultRenderer, HtmlOut, HtmlRenderer};
+/// # use pulldown_cmark::Alignment;
+/// struct ScrollingTables;
+///
+/// impl HtmlRenderer for ScrollingTables {
+///     fn start_table(&self, out: &mut HtmlOut, alignments: &[Alignment]) {
+///         out.block();
+///         out.write("<div class=\"table-scroll\">");
+///         DefaultRenderer.start_table(out, alignments);
+///     }
+///
+///     fn end_table(&self, out: &mut HtmlOut) {
+///         DefaultRenderer.end_table(out);
+///         out.write("</div>\n");
+///     }
+/// }
+/// ```
+///
+/// Inline formatting (emphasis, strong, strikethrough, sub- and superscript) and definition lists
+/// are always written the same way.
+pub trait HtmlRenderer: Send + Sync {
+    fn start_paragraph(&self, out: &mut HtmlOut) {
+        out.block();
+        out.write("<p>");
+    }
+
+    fn end_paragraph(&self, out: &mut HtmlOut) {
+        out.write("</p>\n");
+    }
+
+    fn start_heading(
+        &self,
+        out: &mut HtmlOut,
+        level: HeadingLevel,
+        id: Option<&str>,
+        classes: &[CowStr<'_>],
+        attrs: &[(CowStr<'_>, Option<CowStr<'_>>)],
+    ) {
+        out.block();
+        out.write(&format!("<{}", level));
+        if let Some(id) = id {
+            out.write(" id=\"");
+            out.text(id);
+            out.write("\"");
+        }
+        if !classes.is_empty() {
+            out.write(" class=\"");
+            out.text(&classes.join(" "));
+            out.write("\"");
+        }
+        for (attr, value) in attrs {
+            out.write(" ");
+            out.text(attr);
+            out.write("=\"");
+            out.text(value.as_deref().unwrap_or_default());
+            out.write("\"");
+        }
+        out.write(">");
+    }
+
+    fn end_heading(&self, out: &mut HtmlOut, level: HeadingLevel) {
+        out.write(&format!("</{}>\n", level));
+    }
+
+    fn start_table(&self, out: &mut HtmlOut, _alignments: &[Alignment]) {
+        out.write("<table>");
+    }
+
+    fn end_table(&self, out: &mut HtmlOut) {
+        out.write("</tbody></table>\n");
+    }
+
+    fn start_table_head(&self, out: &mut HtmlOut) {
+        out.write("<thead><tr>");
+    }
+
+    fn end_table_head(&self, out: &mut HtmlOut) {
+        out.write("</tr></thead><tbody>\n");
+    }
+
+    fn start_table_row(&self, out: &mut HtmlOut) {
+        out.write("<tr>");
+    }
+
+    fn end_table_row(&self, out: &mut HtmlOut) {
+        out.write("</tr>\n");
+    }
+
+    fn start_table_cell(&self, out: &mut HtmlOut, cell: TableCell) {
+        out.write(if cell.head { "<th" } else { "<td" });
+        out.write(match cell.alignment {
+            Alignment::Left => " style=\"text-align: left\">",
+            Alignment::Center => " style=\"text-align: center\">",
+            Alignment::Right => " style=\"text-align: right\">",
+            Alignment::None => ">",
+        });
+    }
+
+    fn end_table_cell(&self, out: &mut HtmlOut, cell: TableCell) {
+        out.write(if cell.head { "</th>" } else { "</td>" });
+    }
+
+    fn start_blockquote(&self, out: &mut HtmlOut) {
+        out.block();
+        out.write("<blockquote>\n");
+    }
+
+    fn end_blockquote(&self, out: &mut HtmlOut) {
+        out.write("</blockquote>\n");
+    }
+
+    /// Starts a blockquote marked as an alert, `> [!NOTE]`, writing its title.
+    fn start_alert(&self, out: &mut HtmlOut, alert: &Alert) {
+        out.block();
+        out.write("<div class=\"markdown-alert markdown-alert-");
+        out.text(&alert.kind);
+        out.write("\">\n<p class=\"markdown-alert-title\">");
+        if let Some(icon) = &alert.icon {
+            out.write("<span class=\"markdown-alert-icon\" aria-hidden=\"true\">");
+            out.write(icon);
+            out.write("</span>");
+        }
+        out.text(&alert.title);
+        out.write("</p>\n");
+    }
+
+    fn end_alert(&self, out: &mut HtmlOut, _alert: &Alert) {
+        out.write("</div>\n");
+    }
+
+    fn start_code_block(&self, out: &mut HtmlOut, kind: &CodeBlockKind<'_>) {
+        out.block();
+        let lang = match kind {
+            CodeBlockKind::Fenced(info) => info.split(' ').next().unwrap_or_default(),
+            CodeBlockKind::Indented => "",
+        };
+        if lang.is_empty() {
+            out.write("<pre><code>");
+        } else {
+            out.write("<pre><code class=\"language-");
+            out.text(lang);
+            out.write("\">");
+        }
+    }
+
+    fn end_code_block(&self, out: &mut HtmlOut) {
+        out.write("</code></pre>\n");
+    }
+
+    /// Starts a list, numbered from `start` if it is ordered.
+    fn start_list(&self, out: &mut HtmlOut, start: Option<u64>) {
+        out.block();
+        match start {
+            Some(1) => out.write("<ol>\n"),
+            Some(start) => out.write(&format!("<ol start=\"{}\">\n", start)),
+            None => out.write("<ul>\n"),
+        }
+    }
+
+    fn end_list(&self, out: &mut HtmlOut, ordered: bool) {
+        out.write(if ordered { "</ol>\n" } else { "</ul>\n" });
+    }
+
+    fn start_item(&self, out: &mut HtmlOut) {
+        out.block();
+        out.write("<li>");
+    }
+
+    fn end_item(&self, out: &mut HtmlOut) {
+        out.write("</li>\n");
+    }
+
+    fn start_link(&self, out: &mut HtmlOut, link: &Link<'_>) {
+        out.write("<a href=\"");
+        out.href(link.url);
+        if !link.title.is_empty() {
+            out.write("\" title=\"");
+            out.text(link.title);
+        }
+        if let Some(external) = link.external {
+            if let Some(class) = &external.class {
+                out.write("\" class=\"");
+                out.text(class);
+            }
+            if external.target_blank {
+                out.write("\" target=\"_blank");
+            }
+            if !external.rel.is_empty() {
+                out.write("\" rel=\"");
+                out.text(&external.rel.join(" "));
+            }
+        }
+        out.write("\">");
+    }
+
+    fn end_link(&self, out: &mut HtmlOut) {
+        out.write("</a>");
+    }
+
+    fn image(&self, out: &mut HtmlOut, image: &Image<'_>) {
+        out.write("<img src=\"");
+        out.href(image.url);
+        out.write("\" alt=\"");
+        out.text(image.alt);
+        if !image.title.is_empty() {
+            out.write("\" title=\"");
+            out.text(image.title);
+        }
+        if let Some(size) = image.size {
+            out.write(&format!(
+                "\" width=\"{}\" height=\"{}",
+                size.width, size.height
+            ));
+        }
+        if image.lazy {
+            out.write("\" loading=\"lazy\" decoding=\"async");
+        }
+        out.write("\" />");
+    }
+
+    fn start_footnote_definition(&self, out: &mut HtmlOut, name: &str, number: usize) {
+        out.block();
+        out.write("<div class=\"footnote-definition\" id=\"");
+        out.text(name);
+        out.write(&format!(
+            "\"><sup class=\"footnote-definition-label\">{}</sup>",
+            number
+        ));
+    }
+
+    fn end_footnote_definition(&self, out: &mut HtmlOut) {
+        out.write("</div>\n");
+    }
+}
+
+/// The markup aaska writes by default.
+pub struct DefaultRenderer;
+
+impl HtmlRenderer for DefaultRenderer {}
+
 enum TableState {
     Head,
     Body,
@@ -39,14 +447,19 @@ struct HtmlWriter<'a, I, W, F = fn(&str) -> String> {
     /// Iterator supplying events.
     iter: I,
 
+    /// Events read ahead, to be handled before those of `iter`.
+    replay: VecDeque<Event<'a>>,
+
     /// Writer to write to.
     writer: W,
 
     /// URL resolver for transforming asset URLs
     url_resolver: Option<F>,
 
-    /// Whether or not the last write wrote a newline.
-    end_newline: bool,
+    options: HtmlOptions,
+
+    /// Output of the renderer hooks, also tracking whether the last write wrote a newline.
+    out: HtmlOut,
 
     /// Whether if inside a metadata block (text should not be written)
     in_non_writing_block: bool,
@@ -55,6 +468,8 @@ struct HtmlWriter<'a, I, W, F = fn(&str) -> String> {
     table_alignments: Vec<Alignment>,
     table_cell_index: usize,
     numbers: HashMap<CowStr<'a>, usize>,
+    /// The alert each open blockquote is, if any
+    alerts: Vec<Option<Alert>>,
 }
 
 // Identity function for when no URL resolution is needed
@@ -71,21 +486,70 @@ where
     fn new_with_resolver(iter: I, writer: W, url_resolver: F) -> Self {
         Self {
             iter,
+            replay: VecDeque::new(),
             writer,
             url_resolver: Some(url_resolver),
-            end_newline: true,
+            options: HtmlOptions::default(),
+            out: HtmlOut {
+                html: String::new(),
+                end_newline: true,
+            },
             in_non_writing_block: false,
             table_state: TableState::Head,
             table_alignments: vec![],
             table_cell_index: 0,
             numbers: HashMap::new(),
+            alerts: Vec::new(),
+        }
+    }
+
+    fn next_event(&mut self) -> Option<Event<'a>> {
+        self.replay.pop_front().or_else(|| self.iter.next())
+    }
+
+    /// Reads the `[!KIND]` marker pulldown-cmark leaves in blockquotes of a kind it does not know,
+    /// such as a kind the site adds. The events read are replayed unless they are the marker.
+    fn read_alert_marker(&mut self) -> Option<Alert> {
+        let mut events = Vec::new();
+        let mut marker = String::new();
+        let mut alert = None;
+        while let Some(event) = self.next_event() {
+            let done = match &event {
+                Start(Tag::Paragraph) if events.is_empty() => false,
+                Text(text) if !events.is_empty() => {
+                    marker.push_str(text);
+                    false
+                }
+                SoftBreak | End(TagEnd::Paragraph) => {
+                    alert = marker
+                        .trim_end()
+                        .strip_prefix("[!")
+                        .and_then(|kind| kind.strip_suffix(']'))
+                        .and_then(|kind| Alert::new(&self.options.alerts, kind));
+                    true
+                }
+                _ => true,
+            };
+            events.push(event);
+            if done {
+                break;
+            }
         }
+        if alert.is_none() {
+            for event in events.into_iter().rev() {
+                self.replay.push_front(event);
+            }
+        } else if matches!(events.last(), Some(SoftBreak)) {
+            // The paragraph goes on after the marker
+            self.replay.push_front(Start(Tag::Paragraph));
+        }
+        alert
     }
 
     /// Writes a new line.
     #[inline]
     fn write_newline(&mut self) -> Result<(), W::Error> {
-        self.end_newline = true;
+        self.out.end_newline = true;
         self.writer.write_str("\n")
     }
 
@@ -95,13 +559,13 @@ where
         self.writer.write_str(s)?;
 
         if !s.is_empty() {
-            self.end_newline = s.ends_with('\n');
+            self.out.end_newline = s.ends_with('\n');
         }
         Ok(())
     }
 
     fn run(mut self) -> Result<(), W::Error> {
-        while let Some(event) = self.iter.next() {
+        while let Some(event) = self.next_event() {
             match event {
                 Start(tag) => {
                     self.start_tag(tag)?;
@@ -112,7 +576,7 @@ where
                 Text(text) => {
                     if !self.in_non_writing_block {
                         escape_html_body_text(&mut self.writer, &text)?;
-                        self.end_newline = text.ends_with('\n');
+                        self.out.end_newline = text.ends_with('\n');
                     }
                 }
                 Code(text) => {
@@ -140,7 +604,7 @@ where
                     self.write("<br />\n")?;
                 }
                 Rule => {
-                    if self.end_newline {
+                    if self.out.end_newline {
                         self.write("<hr />\n")?;
                     } else {
                         self.write("\n<hr />\n")?;
@@ -166,16 +630,32 @@ where
         Ok(())
     }
 
+    /// Writes what the renderer hooks wrote.
+    fn flush(&mut self) -> Result<(), W::Error> {
+        let result = self.writer.write_str(&self.out.html);
+        self.out.html.clear();
+        result
+    }
+
+    fn table_cell(&self) -> TableCell {
+        TableCell {
+            head: matches!(self.table_state, TableState::Head),
+            alignment: self
+                .table_alignments
+                .get(self.table_cell_index)
+                .copied()
+                .unwrap_or(Alignment::None),
+            index: self.table_cell_index,
+        }
+    }
+
     /// Writes the start of an HTML tag.
     fn start_tag(&mut self, tag: Tag<'a>) -> Result<(), W::Error> {
         match tag {
             Tag::HtmlBlock => Ok(()),
             Tag::Paragraph => {
-                if self.end_newline {
-                    self.write("<p>")
-                } else {
-                    self.write("\n<p>")
-                }
+                self.options.renderer.start_paragraph(&mut self.out);
+                self.flush()
             }
             Tag::Heading {
                 level,
@@ -183,150 +663,78 @@ where
                 classes,
                 attrs,
             } => {
-                if self.end_newline {
-                    self.write("<")?;
-                } else {
-                    self.write("\n<")?;
-                }
-                write!(&mut self.writer, "{}", level)?;
-                if let Some(id) = id {
-                    self.write(" id=\"")?;
-                    escape_html(&mut self.writer, &id)?;
-                    self.write("\"")?;
-                }
-                let mut classes = classes.iter();
-                if let Some(class) = classes.next() {
-                    self.write(" class=\"")?;
-                    escape_html(&mut self.writer, class)?;
-                    for class in classes {
-                        self.write(" ")?;
-                        escape_html(&mut self.writer, class)?;
-                    }
-                    self.write("\"")?;
-                }
-                for (attr, value) in attrs {
-                    self.write(" ")?;
-                    escape_html(&mut self.writer, &attr)?;
-                    if let Some(val) = value {
-                        self.write("=\"")?;
-                        escape_html(&mut self.writer, &val)?;
-                        self.write("\"")?;
-                    } else {
-                        self.write("=\"\"")?;
-                    }
-                }
-                self.write(">")
+                self.options.renderer.start_heading(
+                    &mut self.out,
+                    level,
+                    id.as_deref(),
+                    &classes,
+                    &attrs,
+                );
+                self.flush()
             }
             Tag::Table(alignments) => {
+                self.options
+                    .renderer
+                    .start_table(&mut self.out, &alignments);
                 self.table_alignments = alignments;
-                self.write("<table>")
+                self.flush()
             }
             Tag::TableHead => {
                 self.table_state = TableState::Head;
                 self.table_cell_index = 0;
-                self.write("<thead><tr>")
+                self.options.renderer.start_table_head(&mut self.out);
+                self.flush()
             }
             Tag::TableRow => {
                 self.table_cell_index = 0;
-                self.write("<tr>")
+                self.options.renderer.start_table_row(&mut self.out);
+                self.flush()
             }
             Tag::TableCell => {
-                match self.table_state {
-                    TableState::Head => {
-                        self.write("<th")?;
-                    }
-                    TableState::Body => {
-                        self.write("<td")?;
-                    }
-                }
-                match self.table_alignments.get(self.table_cell_index) {
-                    Some(&Alignment::Left) => self.write(" style=\"text-align: left\">"),
-                    Some(&Alignment::Center) => self.write(" style=\"text-align: center\">"),
-                    Some(&Alignment::Right) => self.write(" style=\"text-align: right\">"),
-                    _ => self.write(">"),
-                }
+                let cell = self.table_cell();
+                self.options.renderer.start_table_cell(&mut self.out, cell);
+                self.flush()
             }
             Tag::BlockQuote(kind) => {
-                let class_str = match kind {
-                    None => "",
-                    Some(kind) => match kind {
-                        BlockQuoteKind::Note => " class=\"markdown-alert-note\"",
-                        BlockQuoteKind::Tip => " class=\"markdown-alert-tip\"",
-                        BlockQuoteKind::Important => " class=\"markdown-alert-important\"",
-                        BlockQuoteKind::Warning => " class=\"markdown-alert-warning\"",
-                        BlockQuoteKind::Caution => " class=\"markdown-alert-caution\"",
-                    },
+                let alert = match kind {
+                    Some(kind) => Alert::new(&self.options.alerts, alert_kind(kind)),
+                    None => self.read_alert_marker(),
                 };
-                if self.end_newline {
-                    self.write(&format!("<blockquote{}>\n", class_str))
-                } else {
-                    self.write(&format!("\n<blockquote{}>\n", class_str))
-                }
-            }
-            Tag::CodeBlock(info) => {
-                if !self.end_newline {
-                    self.write_newline()?;
-                }
-                match info {
-                    CodeBlockKind::Fenced(info) => {
-                        let lang = info.split(' ').next().unwrap();
-                        if lang.is_empty() {
-                            self.write("<pre><code>")
-                        } else {
-                            self.write("<pre><code class=\"language-")?;
-                            escape_html(&mut self.writer, lang)?;
-                            self.write("\">")
-                        }
-                    }
-                    CodeBlockKind::Indented => self.write("<pre><code>"),
+                match &alert {
+                    Some(alert) => self.options.renderer.start_alert(&mut self.out, alert),
+                    None => self.options.renderer.start_blockquote(&mut self.out),
                 }
+                self.alerts.push(alert);
+                self.flush()
             }
-            Tag::List(Some(1)) => {
-                if self.end_newline {
-                    self.write("<ol>\n")
-                } else {
-                    self.write("\n<ol>\n")
-                }
-            }
-            Tag::List(Some(start)) => {
-                if self.end_newline {
-                    self.write("<ol start=\"")?;
-                } else {
-                    self.write("\n<ol start=\"")?;
-                }
-                write!(&mut self.writer, "{}", start)?;
-                self.write("\">\n")
+            Tag::CodeBlock(kind) => {
+                self.options.renderer.start_code_block(&mut self.out, &kind);
+                self.flush()
             }
-            Tag::List(None) => {
-                if self.end_newline {
-                    self.write("<ul>\n")
-                } else {
-                    self.write("\n<ul>\n")
-                }
+            Tag::List(start) => {
+                self.options.renderer.start_list(&mut self.out, start);
+                self.flush()
             }
             Tag::Item => {
-                if self.end_newline {
-                    self.write("<li>")
-                } else {
-                    self.write("\n<li>")
-                }
+                self.options.renderer.start_item(&mut self.out);
+                self.flush()
             }
             Tag::DefinitionList => {
-                if self.end_newline {
+                if self.out.end_newline {
                     self.write("<dl>\n")
                 } else {
                     self.write("\n<dl>\n")
                 }
             }
             Tag::DefinitionListTitle => {
-                if self.end_newline {
+                if self.out.end_newline {
                     self.write("<dt>")
                 } else {
                     self.write("\n<dt>")
                 }
             }
             Tag::DefinitionListDefinition => {
-                if self.end_newline {
+                if self.out.end_newline {
                     self.write("<dd>")
                 } else {
                     self.write("\n<dd>")
@@ -338,71 +746,62 @@ where
             Tag::Strong => self.write("<strong>"),
             Tag::Strikethrough => self.write("<del>"),
             Tag::Link {
-                link_type: LinkType::Email,
-                dest_url,
-                title,
-                id: _,
-            } => {
-                self.write("<a href=\"mailto:")?;
-                escape_href(&mut self.writer, &dest_url)?;
-                if !title.is_empty() {
-                    self.write("\" title=\"")?;
-                    escape_html(&mut self.writer, &title)?;
-                }
-                self.write("\">")
-            }
-            Tag::Link {
-                link_type: _,
+                link_type,
                 dest_url,
                 title,
                 id: _,
             } => {
-                self.write("<a href=\"")?;
-                let resolved_url = if let Some(ref resolver) = self.url_resolver {
+                let url = if link_type == LinkType::Email {
+                    format!("mailto:{}", dest_url)
+                } else if let Some(ref resolver) = self.url_resolver {
                     resolver(&dest_url)
                 } else {
                     dest_url.to_string()
                 };
-                escape_href(&mut self.writer, &resolved_url)?;
-                if !title.is_empty() {
-                    self.write("\" title=\"")?;
-                    escape_html(&mut self.writer, &title)?;
-                }
-                self.write("\">")
+                let external_links = &self.options.external_links;
+                let external = (link_type != LinkType::Email
+                    && external_links.applies_to(&url, &self.options.base_url))
+                .then_some(external_links);
+                let link = Link {
+                    link_type,
+                    url: &url,
+                    title: &title,
+                    external,
+                };
+                self.options.renderer.start_link(&mut self.out, &link);
+                self.flush()
             }
             Tag::Image {
-                link_type: _,
+                link_type,
                 dest_url,
                 title,
                 id: _,
             } => {
-                self.write("<img src=\"")?;
-                let resolved_url = if let Some(ref resolver) = self.url_resolver {
+                let url = if let Some(ref resolver) = self.url_resolver {
                     resolver(&dest_url)
                 } else {
                     dest_url.to_string()
                 };
-                escape_href(&mut self.writer, &resolved_url)?;
-                self.write("\" alt=\"")?;
-                self.raw_text()?;
-                if !title.is_empty() {
-                    self.write("\" title=\"")?;
-                    escape_html(&mut self.writer, &title)?;
-                }
-                self.write("\" />")
+                let alt = self.raw_text();
+                let images = self.options.images.as_ref();
+                let image = Image {
+                    link_type,
+                    url: &url,
+                    alt: &alt,
+                    title: &title,
+                    size: images.and_then(|images| images.get(dest_url.as_ref()).copied()),
+                    lazy: images.is_some(),
+                };
+                self.options.renderer.image(&mut self.out, &image);
+                self.flush()
             }
             Tag::FootnoteDefinition(name) => {
-                if self.end_newline {
-                    self.write("<div class=\"footnote-definition\" id=\"")?;
-                } else {
-                    self.write("\n<div class=\"footnote-definition\" id=\"")?;
-                }
-                escape_html(&mut self.writer, &name)?;
-                self.write("\"><sup class=\"footnote-definition-label\">")?;
                 let len = self.numbers.len() + 1;
-                let number = *self.numbers.entry(name).or_insert(len);
-                write!(&mut self.writer, "{}", number)?;
-                self.write("</sup>")
+                let number = *self.numbers.entry(name.clone()).or_insert(len);
+                self.options
+                    .renderer
+                    .start_footnote_definition(&mut self.out, &name, number);
+                self.flush()
             }
             Tag::MetadataBlock(_) => {
                 self.in_non_writing_block = true;
@@ -414,50 +813,26 @@ where
     fn end_tag(&mut self, tag: TagEnd) -> Result<(), W::Error> {
         match tag {
             TagEnd::HtmlBlock => {}
-            TagEnd::Paragraph => {
-                self.write("</p>\n")?;
-            }
-            TagEnd::Heading(level) => {
-                self.write("</")?;
-                write!(&mut self.writer, "{}", level)?;
-                self.write(">\n")?;
-            }
-            TagEnd::Table => {
-                self.write("</tbody></table>\n")?;
-            }
+            TagEnd::Paragraph => self.options.renderer.end_paragraph(&mut self.out),
+            TagEnd::Heading(level) => self.options.renderer.end_heading(&mut self.out, level),
+            TagEnd::Table => self.options.renderer.end_table(&mut self.out),
             TagEnd::TableHead => {
-                self.write("</tr></thead><tbody>\n")?;
+                self.options.renderer.end_table_head(&mut self.out);
                 self.table_state = TableState::Body;
             }
-            TagEnd::TableRow => {
-                self.write("</tr>\n")?;
-            }
+            TagEnd::TableRow => self.options.renderer.end_table_row(&mut self.out),
             TagEnd::TableCell => {
-                match self.table_state {
-                    TableState::Head => {
-                        self.write("</th>")?;
-                    }
-                    TableState::Body => {
-                        self.write("</td>")?;
-                    }
-                }
+                let cell = self.table_cell();
+                self.options.renderer.end_table_cell(&mut self.out, cell);
                 self.table_cell_index += 1;
             }
-            TagEnd::BlockQuote(_) => {
-                self.write("</blockquote>\n")?;
-            }
-            TagEnd::CodeBlock => {
-                self.write("</code></pre>\n")?;
-            }
-            TagEnd::List(true) => {
-                self.write("</ol>\n")?;
-            }
-            TagEnd::List(false) => {
-                self.write("</ul>\n")?;
-            }
-            TagEnd::Item => {
-                self.write("</li>\n")?;
-            }
+            TagEnd::BlockQuote(_) => match self.alerts.pop().flatten() {
+                Some(alert) => self.options.renderer.end_alert(&mut self.out, &alert),
+                None => self.options.renderer.end_blockquote(&mut self.out),
+            },
+            TagEnd::CodeBlock => self.options.renderer.end_code_block(&mut self.out),
+            TagEnd::List(ordered) => self.options.renderer.end_list(&mut self.out, ordered),
+            TagEnd::Item => self.options.renderer.end_item(&mut self.out),
             TagEnd::DefinitionList => {
                 self.write("</dl>\n")?;
             }
@@ -482,24 +857,23 @@ where
             TagEnd::Strikethrough => {
                 self.write("</del>")?;
             }
-            TagEnd::Link => {
-                self.write("</a>")?;
-            }
+            TagEnd::Link => self.options.renderer.end_link(&mut self.out),
             TagEnd::Image => (), // shouldn't happen, handled in start
             TagEnd::FootnoteDefinition => {
-                self.write("</div>\n")?;
+                self.options.renderer.end_footnote_definition(&mut self.out)
             }
             TagEnd::MetadataBlock(_) => {
                 self.in_non_writing_block = false;
             }
         }
-        Ok(())
+        self.flush()
     }
 
-    // run raw text, consuming end tag
-    fn raw_text(&mut self) -> Result<(), W::Error> {
+    // collect raw text, consuming end tag
+    fn raw_text(&mut self) -> String {
+        let mut text = String::new();
         let mut nest = 0;
-        while let Some(event) = self.iter.next() {
+        while let Some(event) = self.next_event() {
             match event {
                 Start(_) => nest += 1,
                 End(_) => {
@@ -509,35 +883,28 @@ where
                     nest -= 1;
                 }
                 Html(_) => {}
-                InlineHtml(text) | Code(text) | Text(text) => {
-                    // Don't use escape_html_body_text here.
-                    // The output of this function is used in the `alt` attribute.
-                    escape_html(&mut self.writer, &text)?;
-                    self.end_newline = text.ends_with('\n');
-                }
-                InlineMath(text) => {
-                    self.write("$")?;
-                    escape_html(&mut self.writer, &text)?;
-                    self.write("$")?;
+                InlineHtml(s) | Code(s) | Text(s) => text.push_str(&s),
+                InlineMath(s) => {
+                    text.push('$');
+                    text.push_str(&s);
+                    text.push('$');
                 }
-                DisplayMath(text) => {
-                    self.write("$$")?;
-                    escape_html(&mut self.writer, &text)?;
-                    self.write("$$")?;
-                }
-                SoftBreak | HardBreak | Rule => {
-                    self.write(" ")?;
+                DisplayMath(s) => {
+                    text.push_str("$$");
+                    text.push_str(&s);
+                    text.push_str("$$");
                 }
+                SoftBreak | HardBreak | Rule => text.push(' '),
                 FootnoteReference(name) => {
                     let len = self.numbers.len() + 1;
                     let number = *self.numbers.entry(name).or_insert(len);
-                    write!(&mut self.writer, "[{}]", number)?;
+                    text.push_str(&format!("[{}]", number));
                 }
-                TaskListMarker(true) => self.write("[x]")?,
-                TaskListMarker(false) => self.write("[ ]")?,
+                TaskListMarker(true) => text.push_str("[x]"),
+                TaskListMarker(false) => text.push_str("[ ]"),
             }
         }
-        Ok(())
+        text
     }
 }
 
@@ -662,3 +1029,148 @@ where
         .run()
         .unwrap()
 }
+
+/// Like [`push_html_with_resolver`], with the additions described by `options`.
+pub fn push_html_with_options<'a, I, F>(
+    s: &mut String,
+    iter: I,
+    url_resolver: F,
+    options: HtmlOptions,
+) where
+    I: Iterator<Item = Event<'a>>,
+    F: Fn(&str) -> String,
+{
+    let mut writer = HtmlWriter::new_with_resolver(iter, FmtWriter(s), url_resolver);
+    writer.options = options;
+    writer.run().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct ScrollingTables;
+
+    impl HtmlRenderer for ScrollingTables {
+        fn start_table(&self, out: &mut HtmlOut, alignments: &[Alignment]) {
+            out.block();
+            out.write("<div class=\"table-scroll\">");
+            DefaultRenderer.start_table(out, alignments);
+        }
+
+        fn end_table(&self, out: &mut HtmlOut) {
+            DefaultRenderer.end_table(out);
+            out.write("</div>\n");
+        }
+    }
+
+    fn render(md: &str, alerts: AlertsConfig) -> String {
+        let mut html = String::new();
+        let parser = pulldown_cmark::Parser::new_ext(md, crate::MD_OPTIONS);
+        let options = HtmlOptions {
+            alerts,
+            ..Default::default()
+        };
+        push_html_with_options(&mut html, parser, identity_resolver, options);
+        html
+    }
+
+    #[test]
+    fn test_alerts() {
+        assert_eq!(
+            render("> [!NOTE]\n> Be *careful*\n", AlertsConfig::default()),
+            "<div class=\"markdown-alert markdown-alert-note\">\n\
+             <p class=\"markdown-alert-title\">Note</p>\n\
+             <p>Be <em>careful</em></p>\n</div>\n"
+        );
+
+        let config: AlertsConfig = toml::from_str(
+            r#"
+            icons = true
+            kinds.tip.title = "Astuce"
+            kinds.example.icon = "<svg/>"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            render("> [!TIP]\n> Try this\n", config.clone()),
+            "<div class=\"markdown-alert markdown-alert-tip\">\n\
+             <p class=\"markdown-alert-title\">\
+             <span class=\"markdown-alert-icon\" aria-hidden=\"true\">\u{1F4A1}</span>Astuce</p>\n\
+             <p>Try this</p>\n</div>\n"
+        );
+        assert_eq!(
+            render("> > [!Example]\n> >\n> > Run it\n", config.clone()),
+            "<blockquote>\n<div class=\"markdown-alert markdown-alert-example\">\n\
+             <p class=\"markdown-alert-title\">\
+             <span class=\"markdown-alert-icon\" aria-hidden=\"true\"><svg/></span>Example</p>\n\
+             <p>Run it</p>\n</div>\n</blockquote>\n"
+        );
+        assert_eq!(
+            render("> [!OTHER]\n> Quoted\n", config),
+            "<blockquote>\n<p>[!OTHER]\nQuoted</p>\n</blockquote>\n"
+        );
+
+        let config: AlertsConfig = toml::from_str(r#"kinds."änderung" = {}"#).unwrap();
+        assert_eq!(
+            render("> [!ÄNDERUNG]\n> Neu\n", config),
+            "<div class=\"markdown-alert markdown-alert-änderung\">\n\
+             <p class=\"markdown-alert-title\">Änderung</p>\n\
+             <p>Neu</p>\n</div>\n"
+        );
+    }
+
+    #[test]
+    fn test_renderer() {
+        let md = "Text\n\n| a |\n|---|\n| 1 |\n";
+        let render_with = |renderer: Arc<dyn HtmlRenderer>| {
+            let mut html = String::new();
+            let parser = pulldown_cmark::Parser::new_ext(md, crate::MD_OPTIONS);
+            let options = HtmlOptions {
+                renderer,
+                ..Default::default()
+            };
+            push_html_with_options(&mut html, parser, identity_resolver, options);
+            html
+        };
+        let table = "<table><thead><tr><th>a</th></tr></thead><tbody>\n<tr><td>1</td></tr>\n</tbody></table>\n";
+        assert_eq!(
+            render_with(Arc::new(DefaultRenderer)),
+            format!("<p>Text</p>\n{}", table)
+        );
+        assert_eq!(
+            render_with(Arc::new(ScrollingTables)),
+            format!("<p>Text</p>\n<div class=\"table-scroll\">{}</div>\n", table)
+        );
+    }
+
+    #[test]
+    fn test_images() {
+        let md = "![A cat](cat.png \"Cat\") ![Remote](https://example.com/a.png)\n";
+        let render_with = |images| {
+            let mut html = String::new();
+            let parser = pulldown_cmark::Parser::new_ext(md, crate::MD_OPTIONS);
+            let options = HtmlOptions {
+                images,
+                ..Default::default()
+            };
+            push_html_with_options(&mut html, parser, identity_resolver, options);
+            html
+        };
+        let size = ImageSize {
+            width: 640,
+            height: 480,
+        };
+        assert_eq!(
+            render_with(Some(HashMap::from([("cat.png".to_string(), size)]))),
+            "<p><img src=\"cat.png\" alt=\"A cat\" title=\"Cat\" width=\"640\" height=\"480\" \
+             loading=\"lazy\" decoding=\"async\" /> <img src=\"https://example.com/a.png\" \
+             alt=\"Remote\" loading=\"lazy\" decoding=\"async\" /></p>\n"
+        );
+        assert_eq!(
+            render_with(None),
+            "<p><img src=\"cat.png\" alt=\"A cat\" title=\"Cat\" /> \
+             <img src=\"https://example.com/a.png\" alt=\"Remote\" /></p>\n"
+        );
+    }
+}
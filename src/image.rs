@@ -0,0 +1,196 @@
+//! Intrinsic dimensions of images, read from their headers, so that pages can reserve the space an
+//! image takes before it loads.
+
+use crate::db::{Db, SourceFile};
+use crate::path::SrcPath;
+use picante::PicanteResult;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct ImageSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Size of an image asset, or `None` if it is not an image in a format we can read.
+#[picante::tracked]
+pub async fn image_size<DB: Db>(db: &DB, file: SourceFile) -> PicanteResult<Option<ImageSize>> {
+    let _timing = crate::timings::query(db, "image_size", file);
+    let is_svg = file.path(db)?.ext().eq_ignore_ascii_case(".svg");
+    let contents = file.contents(db)?;
+    // An SVG is parsed whole, and either may be large
+    let size = crate::cpu::run(move || {
+        if is_svg {
+            svg_size(&String::from_utf8_lossy(&contents))
+        } else {
+            raster_size(&contents)
+        }
+    })
+    .await;
+    Ok(size)
+}
+
+/// Whether an asset is an image in a format [`image_size`] can read, by its extension.
+pub fn is_image(path: &SrcPath) -> bool {
+    [".png", ".jpg", ".jpeg", ".gif", ".webp", ".svg"]
+        .iter()
+        .any(|ext| path.ext().eq_ignore_ascii_case(ext))
+}
+
+/// Size of a PNG, JPEG, GIF or WebP image.
+pub fn raster_size(bytes: &[u8]) -> Option<ImageSize> {
+    let size = |width, height| Some(ImageSize { width, height });
+    let be32 = |at: usize| Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?));
+    let le16 = |at: usize| Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32);
+    let le24 = |at: usize| {
+        let b = bytes.get(at..at + 3)?;
+        Some(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16)
+    };
+
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        // The IHDR chunk always comes first
+        return size(be32(16)?, be32(20)?);
+    }
+    if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        return size(le16(6)?, le16(8)?);
+    }
+    if bytes.starts_with(b"\xFF\xD8") {
+        return jpeg_size(bytes);
+    }
+    if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        return match bytes.get(12..16)? {
+            b"VP8 " => size(le16(26)? & 0x3FFF, le16(28)? & 0x3FFF),
+            b"VP8L" => {
+                let bits = u32::from_le_bytes(bytes.get(21..25)?.try_into().ok()?);
+                size((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1)
+            }
+            b"VP8X" => size(le24(24)? + 1, le24(27)? + 1),
+            _ => None,
+        };
+    }
+    None
+}
+
+/// Reads the size from the start-of-frame segment of a JPEG.
+fn jpeg_size(bytes: &[u8]) -> Option<ImageSize> {
+    let be16 = |at: usize| Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32);
+    let mut i = 2;
+    loop {
+        // Markers may be padded with any number of 0xFF
+        while *bytes.get(i)? == 0xFF {
+            i += 1;
+        }
+        let marker = *bytes.get(i)?;
+        i += 1;
+        match marker {
+            // Markers without a segment
+            0x01 | 0xD0..=0xD8 => continue,
+            // Start of frame, apart from DHT (C4), JPG (C8) and DAC (CC)
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                return Some(ImageSize {
+                    height: be16(i + 3)?,
+                    width: be16(i + 5)?,
+                });
+            }
+            // End of image, or start of scan: no frame header ahead
+            0xD9 | 0xDA => return None,
+            _ => i += be16(i)? as usize,
+        }
+    }
+}
+
+/// Size of an SVG image, from the `width` and `height` of its root element if they are in
+/// pixels, or else from its `viewBox`.
+pub fn svg_size(svg: &str) -> Option<ImageSize> {
+    let start = svg.find("<svg")?;
+    let tag = &svg[start..start + svg[start..].find('>')?];
+    let attr = |name: &str| {
+        let mut rest = tag;
+        loop {
+            let at = rest.find(name)?;
+            let before = rest[..at].chars().last();
+            rest = &rest[at + name.len()..];
+            if !before.is_some_and(char::is_whitespace) {
+                continue;
+            }
+            let Some(value) = rest.trim_start().strip_prefix('=') else {
+                continue;
+            };
+            let value = value.trim_start();
+            let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            let value = &value[1..];
+            return Some(&value[..value.find(quote)?]);
+        }
+    };
+    let pixels = |value: &str| {
+        let value = value.trim();
+        let number = value.strip_suffix("px").unwrap_or(value);
+        number.parse::<f64>().ok().filter(|n| *n > 0.0)
+    };
+    let (width, height) = match (
+        attr("width").and_then(pixels),
+        attr("height").and_then(pixels),
+    ) {
+        (Some(width), Some(height)) => (width, height),
+        _ => {
+            let view_box: Vec<f64> = attr("viewBox")?
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|s| !s.is_empty())
+                .map(|n| n.parse().ok())
+                .collect::<Option<_>>()?;
+            match view_box[..] {
+                [_, _, width, height] if width > 0.0 && height > 0.0 => (width, height),
+                _ => return None,
+            }
+        }
+    };
+    Some(ImageSize {
+        width: width.round() as u32,
+        height: height.round() as u32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(width: u32, height: u32) -> Option<ImageSize> {
+        Some(ImageSize { width, height })
+    }
+
+    #[test]
+    fn test_raster_size() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend(640u32.to_be_bytes());
+        png.extend(480u32.to_be_bytes());
+        assert_eq!(raster_size(&png), size(640, 480));
+
+        assert_eq!(raster_size(b"GIF89a\x20\x00\x10\x00"), size(32, 16));
+
+        let jpeg = [
+            0xFF, 0xD8, // start of image
+            0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, // APP0, skipped
+            0xFF, 0xC0, 0x00, 0x11, 0x08, 0x01, 0x2C, 0x02, 0x58, // SOF0, 600x300
+        ];
+        assert_eq!(raster_size(&jpeg), size(600, 300));
+
+        let mut webp = b"RIFF\0\0\0\0WEBPVP8X\x0a\0\0\0\0\0\0\0".to_vec();
+        webp.extend([0x1F, 0x03, 0x00, 0xC7, 0x00, 0x00]);
+        assert_eq!(raster_size(&webp), size(800, 200));
+
+        assert_eq!(raster_size(b"not an image"), None);
+        assert_eq!(raster_size(b"\x89PNG\r\n\x1a\n"), None);
+    }
+
+    #[test]
+    fn test_svg_size() {
+        assert_eq!(
+            svg_size(r#"<svg xmlns="http://www.w3.org/2000/svg" width="24" height="12px">"#),
+            size(24, 12)
+        );
+        assert_eq!(
+            svg_size(r#"<?xml version="1.0"?><svg width="100%" viewBox="0 0 300.4 150">"#),
+            size(300, 150)
+        );
+        assert_eq!(svg_size(r#"<svg stroke-width="2">"#), None);
+    }
+}
@@ -0,0 +1,154 @@
+//! Layouts wrapping rendered markdown into full HTML documents.
+
+use crate::backlinks::Backlink;
+use crate::config::SiteConfig;
+use crate::paginate::Pager;
+use crate::site::PageMeta;
+use maud::{DOCTYPE, Markup, PreEscaped, html};
+
+/// `url` is the site-relative URL of the document, which its canonical link gives in full.
+fn document(site: &SiteConfig, title: &str, url: &str, body: Markup) -> String {
+    html! {
+        (DOCTYPE)
+        html {
+            head {
+                meta charset="utf-8";
+                meta name="viewport" content="width=device-width, initial-scale=1";
+                title { (title) }
+                link rel="canonical" href=(site.absolute_url(url));
+            }
+            body { (body) }
+        }
+    }
+    .into_string()
+}
+
+pub fn page(site: &SiteConfig, meta: &PageMeta, content: &str, backlinks: &[Backlink]) -> String {
+    let body = html! {
+        main {
+            article {
+                h1 { (meta.title()) }
+                @if let Some(date) = meta.front_matter.date {
+                    time datetime=(date) { (date) }
+                }
+                (PreEscaped(content))
+            }
+            @if !backlinks.is_empty() {
+                aside class="backlinks" {
+                    h2 { "Links to this page" }
+                    ul {
+                        @for backlink in backlinks {
+                            li {
+                                a href=(backlink.url) { (backlink.title) }
+                                @if !backlink.snippet.is_empty() {
+                                    p { (backlink.snippet) }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    };
+    document(site, meta.title(), &meta.url, body)
+}
+
+/// A listing page, for sections and taxonomy terms. `intro` is the rendered content of the
+/// section index, if any.
+pub fn list(site: &SiteConfig, title: &str, intro: &str, pager: &Pager) -> String {
+    let body = html! {
+        main {
+            h1 { (title) }
+            (PreEscaped(intro))
+            ul {
+                @for page in &pager.pages {
+                    li {
+                        a href=(page.url) { (page.title) }
+                        @if let Some(date) = page.date {
+                            " " time datetime=(date) { (date) }
+                        }
+                    }
+                }
+            }
+            @if pager.total > 1 {
+                nav class="pagination" {
+                    a href=(pager.first) { "First" }
+                    @if let Some(prev) = &pager.prev {
+                        " " a rel="prev" href=(prev) { "Previous" }
+                    }
+                    " " span { (pager.index) " / " (pager.total) }
+                    @if let Some(next) = &pager.next {
+                        " " a rel="next" href=(next) { "Next" }
+                    }
+                    " " a href=(pager.last) { "Last" }
+                }
+            }
+        }
+    };
+    document(site, title, &pager.url, body)
+}
+
+/// A page redirecting to `to`, used for URLs that must exist but have a canonical location
+/// elsewhere, such as `page/1/` of a listing.
+pub fn redirect(site: &SiteConfig, to: &str) -> String {
+    html! {
+        (DOCTYPE)
+        html {
+            head {
+                meta charset="utf-8";
+                title { "Redirecting..." }
+                link rel="canonical" href=(site.absolute_url(to));
+                meta http-equiv="refresh" content=(format!("0; url={}", to));
+            }
+            body {
+                a href=(to) { "Click here if you are not redirected." }
+            }
+        }
+    }
+    .into_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical() {
+        let site = SiteConfig {
+            base_url: "https://example.com/".to_string(),
+            ..Default::default()
+        };
+        let meta = PageMeta {
+            src: crate::path::SrcPath::from_relaxed_path("blog/post.md", "/site/content"),
+            url: "/blog/post/".to_string(),
+            front_matter: Default::default(),
+        };
+        let page = page(&site, &meta, "<p>Hi</p>", &[]);
+        assert!(
+            page.contains(r#"<link rel="canonical" href="https://example.com/blog/post/">"#),
+            "{}",
+            page
+        );
+
+        let pager = Pager {
+            index: 2,
+            total: 2,
+            url: "/blog/page/2/".to_string(),
+            pages: Vec::new(),
+            first: "/blog/".to_string(),
+            last: "/blog/page/2/".to_string(),
+            prev: Some("/blog/".to_string()),
+            next: None,
+        };
+        let list = list(&site, "Blog", "", &pager);
+        assert!(
+            list.contains(r#"<link rel="canonical" href="https://example.com/blog/page/2/">"#),
+            "{}",
+            list
+        );
+
+        let redirect = redirect(&site, "/blog/");
+        assert!(redirect.contains(r#"<link rel="canonical" href="https://example.com/blog/">"#));
+        assert!(redirect.contains(r#"content="0; url=/blog/""#));
+    }
+}
@@ -1,44 +1,173 @@
-use std::sync::OnceLock;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, LazyLock, Mutex, OnceLock, RwLock};
 
+pub mod backlinks;
+pub mod build;
+pub mod collections;
+pub mod config;
+pub mod cpu;
 pub mod db;
+pub mod diagnostics;
+pub mod encoding;
+pub mod frontmatter;
 pub mod html;
+pub mod image;
+pub mod layout;
+pub mod manifest;
+pub mod minify;
+pub mod paginate;
 pub mod path;
+pub mod search;
+pub mod shortcode;
+pub mod site;
+pub mod sitemap;
+pub mod timings;
+pub mod vfs;
+pub mod wikilink;
 pub(crate) mod internal_prelude {
     pub use tracing::{debug, error, info, trace, warn};
 }
 
 pub mod prelude {}
 
+use crate::config::SiteConfig;
 use crate::path::SrcPath;
+use crate::shortcode::ShortcodeRegistry;
 
 struct Aaska {}
 
+/// Everything a build needs besides its inputs. Each database has its own, see
+/// [`db::AaskaDb::with_config`].
 struct Config {
-    md_options: pulldown_cmark::Options,
+    site: SiteConfig,
+    shortcodes: ShortcodeRegistry,
+    renderer: Arc<dyn html::HtmlRenderer>,
+    sandbox: path::Sandbox,
+    vfs: Arc<dyn vfs::Vfs>,
+    /// Every file loaded into a [`db::SourceFile`], read again at the start of each build
+    sources: Mutex<HashSet<SrcPath>>,
+    /// Number of times [`site::render_page`] ran, as opposed to being answered from the cache
+    rendered_pages: AtomicUsize,
+    timings: timings::Timings,
 }
 
+impl Config {
+    fn new(
+        site: SiteConfig,
+        shortcodes: ShortcodeRegistry,
+        renderer: Arc<dyn html::HtmlRenderer>,
+        vfs: Arc<dyn vfs::Vfs>,
+    ) -> Self {
+        Self {
+            sandbox: site.sandbox(vfs.as_ref()),
+            site,
+            shortcodes,
+            renderer,
+            vfs,
+            sources: Mutex::default(),
+            rendered_pages: AtomicUsize::new(0),
+            timings: timings::Timings::default(),
+        }
+    }
+}
+
+const MD_OPTIONS: pulldown_cmark::Options = pulldown_cmark::Options::all();
+
 // Chonk is now a regular struct returned by render_chonk
 #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct Chonk {
     pub html: String,
     pub assets: Vec<SrcPath>,
+    /// Assets that were processed, with the hashed file name they are published under
+    pub hashed_assets: Vec<(SrcPath, String)>,
+    /// Other pages linked from this one
+    pub page_links: Vec<crate::backlinks::PageLink>,
+    /// Shortcode templates used by the page, and the files they include
+    pub shortcode_templates: Vec<SrcPath>,
+    pub headings: Vec<Heading>,
+    /// Problems found while rendering, see [`diagnostics::page_diagnostics`]
+    pub diagnostics: Vec<diagnostics::Diagnostic>,
     // other fields when we need to track metadata
     pub og_srcpath: SrcPath,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Heading {
+    pub level: u8,
+    /// Anchor of the heading, either explicit (`# Title {#id}`) or derived from its text
+    pub id: String,
+    pub text: String,
+}
+
+/// A file to be written to the output directory.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct OutputFile {
+    /// Path relative to the output directory
+    pub path: String,
+    pub contents: OutputContents,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum OutputContents {
+    Generated(Vec<u8>),
+    /// Copied verbatim from a source file, so large assets are not held in the query cache
+    Copied(SrcPath),
+}
+
+impl OutputFile {
+    pub fn generated(path: impl Into<String>, contents: impl Into<Vec<u8>>) -> Self {
+        Self {
+            path: path.into(),
+            contents: OutputContents::Generated(contents.into()),
+        }
+    }
+}
+
 struct Asset {
     og_uri: String,
 }
 
-static CONFIG: OnceLock<Config> = OnceLock::new();
+/// Configuration of each database, by runtime. Queries only get a `&DB`, so this is how they
+/// find the configuration of the database they run in. Snapshots share the runtime id of their
+/// database, and with it its configuration.
+static CONFIGS: LazyLock<RwLock<HashMap<picante::RuntimeId, Arc<Config>>>> =
+    LazyLock::new(Default::default);
+
+/// Keeps the configuration of a database registered for as long as the database lives. Only
+/// [`db::AaskaDb::with_config`] makes one, so there is no database without a configuration.
+pub struct ConfigHandle {
+    runtime: OnceLock<picante::RuntimeId>,
+}
+
+impl ConfigHandle {
+    pub(crate) fn new() -> Self {
+        Self {
+            runtime: OnceLock::new(),
+        }
+    }
+
+    fn register<DB: picante::HasRuntime>(&self, db: &DB, config: Config) {
+        let id = db.runtime().id();
+        self.runtime.set(id).expect("Config already registered");
+        CONFIGS.write().unwrap().insert(id, Arc::new(config));
+    }
+}
 
-pub fn init() {
-    let config = Config {
-        md_options: pulldown_cmark::Options::all(),
-    };
-    CONFIG.set(config).ok().expect("Config already initialized");
+impl Drop for ConfigHandle {
+    fn drop(&mut self) {
+        if let Some(id) = self.runtime.get() {
+            CONFIGS.write().unwrap().remove(id);
+        }
+    }
 }
 
-fn config<'a>() -> &'a Config {
-    CONFIG.get().expect("Config not initialized")
+fn config<DB: picante::HasRuntime + ?Sized>(db: &DB) -> Arc<Config> {
+    let id = db.runtime().id();
+    CONFIGS
+        .read()
+        .unwrap()
+        .get(&id)
+        .cloned()
+        .expect("Config not registered, the database was dropped")
 }
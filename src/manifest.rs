@@ -0,0 +1,165 @@
+//! `manifest.json`, describing every output of a build for other tools: where each file comes from,
+//! what it was built from, and where each asset is published under its hashed name. A service
+//! worker can precache its list of outputs, and a backend can find `static/app.css` as
+//! `blog/app.3f2a1b9c.css` without parsing HTML.
+//!
+//! Sources are given by their path relative to the site root, so that files of different
+//! directories, such as `content/logo.png` and `static/logo.png`, are told apart.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use crate::OutputFile;
+use crate::path::SrcPath;
+
+/// Path of the manifest in the output directory.
+pub const MANIFEST_PATH: &str = "manifest.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    /// Every output but the manifest itself, by path relative to the output directory
+    pub outputs: BTreeMap<String, ManifestEntry>,
+    /// Every processed asset, by source path
+    pub assets: BTreeMap<String, ManifestAsset>,
+    /// Site root, which source paths are relative to
+    #[serde(skip)]
+    root: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ManifestEntry {
+    /// Source the output is rendered or copied from. Listings, feeds and indexes built from many
+    /// pages have none.
+    pub source: Option<String>,
+    /// Other source files the output was built from, such as images and shortcode templates
+    pub dependencies: BTreeSet<String>,
+    /// SHA-256 of the contents, in hex
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ManifestAsset {
+    /// File name the asset is published under, with the hash of its contents
+    pub hashed_name: String,
+    /// Paths the asset is published at, relative to the output directory. Assets are published
+    /// next to each page using them.
+    pub outputs: BTreeSet<String>,
+}
+
+impl Manifest {
+    /// A manifest for the site at `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Records where an output comes from. Outputs that are not recorded still get an entry,
+    /// without a source, when [`Manifest::finish`] hashes them.
+    pub fn record(
+        &mut self,
+        output: &OutputFile,
+        source: &SrcPath,
+        dependencies: impl IntoIterator<Item = SrcPath>,
+    ) {
+        let source_path = self.source_path(source);
+        let dependencies: Vec<String> = dependencies
+            .into_iter()
+            .filter(|dep| dep != source)
+            .map(|dep| self.source_path(&dep))
+            .collect();
+        let entry = self.outputs.entry(output.path.clone()).or_default();
+        entry.source = Some(source_path);
+        entry.dependencies.extend(dependencies);
+    }
+
+    /// Records that an asset is published at `output`, a path relative to the output directory.
+    pub fn record_asset(&mut self, source: &SrcPath, output: &str) {
+        let source_path = self.source_path(source);
+        let entry = self.assets.entry(source_path).or_default();
+        entry.hashed_name = output.rsplit('/').next().unwrap_or(output).to_string();
+        entry.outputs.insert(output.to_string());
+    }
+
+    fn source_path(&self, source: &SrcPath) -> String {
+        let path: &Path = source.as_ref();
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Hashes the contents of the outputs and serializes the manifest. Recorded outputs that are
+    /// not part of `files` are dropped.
+    pub fn finish(mut self, files: &BTreeMap<String, Vec<u8>>) -> OutputFile {
+        let mut outputs = std::mem::take(&mut self.outputs);
+        for (path, contents) in files {
+            let mut entry = outputs.remove(path).unwrap_or_default();
+            entry.hash = sha256_hex(contents);
+            self.outputs.insert(path.clone(), entry);
+        }
+        OutputFile::generated(
+            MANIFEST_PATH,
+            serde_json::to_vec_pretty(&self).expect("manifest is serializable"),
+        )
+    }
+}
+
+fn sha256_hex(contents: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(contents)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let page = SrcPath::from_relaxed_path("blog/post.md", "/site/content");
+        let image = SrcPath::from_relaxed_path("blog/cat.png", "/site/content");
+        let logo = SrcPath::from_relaxed_path("cat.png", "/site/static");
+        let html = OutputFile::generated("blog/post/index.html", "<p>Post</p>");
+
+        let mut manifest = Manifest::new("/site");
+        manifest.record(&html, &page, [page.clone(), image.clone()]);
+        manifest.record_asset(&image, "blog/post/cat.0123abcd.png");
+        manifest.record_asset(&image, "blog/other/cat.0123abcd.png");
+        manifest.record_asset(&logo, "cat.4567cdef.png");
+
+        let files = BTreeMap::from([
+            ("blog/post/index.html".to_string(), b"<p>Post</p>".to_vec()),
+            ("sitemap.xml".to_string(), b"<urlset/>".to_vec()),
+        ]);
+        let OutputFile { path, contents } = manifest.finish(&files);
+        assert_eq!(path, MANIFEST_PATH);
+        let crate::OutputContents::Generated(json) = contents else {
+            panic!("manifest is generated");
+        };
+        let manifest: Manifest = serde_json::from_slice(&json).unwrap();
+
+        let entry = &manifest.outputs["blog/post/index.html"];
+        assert_eq!(entry.source.as_deref(), Some("content/blog/post.md"));
+        assert_eq!(
+            entry.dependencies.iter().collect::<Vec<_>>(),
+            vec!["content/blog/cat.png"]
+        );
+        assert_eq!(entry.hash.len(), 64);
+        assert_eq!(manifest.outputs["sitemap.xml"].source, None);
+
+        let asset = &manifest.assets["content/blog/cat.png"];
+        assert_eq!(asset.hashed_name, "cat.0123abcd.png");
+        assert_eq!(
+            asset.outputs.iter().collect::<Vec<_>>(),
+            vec!["blog/other/cat.0123abcd.png", "blog/post/cat.0123abcd.png"]
+        );
+        assert_eq!(
+            manifest.assets["static/cat.png"].hashed_name,
+            "cat.4567cdef.png"
+        );
+    }
+}
@@ -0,0 +1,729 @@
+//! Minification of the HTML documents a build writes. Whitespace is collapsed outside of `<pre>`
+//! and `<textarea>`, comments are dropped, attributes lose their quotes and end tags are omitted
+//! where the HTML spec allows it, and inline styles and scripts are minified.
+//!
+//! Minified pages are a query over the rendered page, so a page is only minified again when its
+//! HTML changes. Minifying runs on the rayon pool, see [`crate::cpu`].
+
+use crate::OutputContents;
+use crate::OutputFile;
+use crate::db::{Db, SourceFile};
+use picante::PicanteResult;
+
+/// Whether the build minifies its HTML, see [`crate::build::Profile`].
+#[picante::input]
+pub struct MinifyOptions {
+    pub enabled: bool,
+}
+
+/// [`crate::site::render_page`], minified if enabled.
+#[picante::tracked]
+pub async fn minified_page<DB: Db>(db: &DB, md_file: SourceFile) -> PicanteResult<Vec<OutputFile>> {
+    let _timing = crate::timings::query(db, "minified_page", md_file);
+    let outputs = crate::site::render_page(db, md_file).await?;
+    minify_outputs(db, outputs).await
+}
+
+/// [`crate::collections::list_pages`], minified if enabled.
+#[picante::tracked]
+pub async fn minified_listings<DB: Db>(db: &DB) -> PicanteResult<Vec<OutputFile>> {
+    let _timing = crate::timings::query(db, "minified_listings", ());
+    let outputs = crate::collections::list_pages(db).await?;
+    minify_outputs(db, outputs).await
+}
+
+async fn minify_outputs<DB: Db>(
+    db: &DB,
+    outputs: Vec<OutputFile>,
+) -> PicanteResult<Vec<OutputFile>> {
+    if !MinifyOptions::enabled(db)?.unwrap_or(false) {
+        return Ok(outputs);
+    }
+    let minified = crate::cpu::run(move || {
+        outputs
+            .into_iter()
+            .map(|output| match output.contents {
+                OutputContents::Generated(bytes) if output.path.ends_with(".html") => {
+                    let contents = match String::from_utf8(bytes) {
+                        Ok(page) => html(&page).into_bytes(),
+                        Err(e) => e.into_bytes(),
+                    };
+                    OutputFile::generated(output.path, contents)
+                }
+                _ => output,
+            })
+            .collect()
+    })
+    .await;
+    Ok(minified)
+}
+
+/// Elements whose surrounding whitespace is not rendered.
+const BLOCKS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "base",
+    "blockquote",
+    "body",
+    "caption",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "legend",
+    "li",
+    "link",
+    "main",
+    "menu",
+    "meta",
+    "nav",
+    "noscript",
+    "ol",
+    "p",
+    "pre",
+    "script",
+    "section",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "ul",
+];
+
+/// Elements without an end tag.
+const VOIDS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Elements whose start implicitly closes an open `<p>`.
+const P_CLOSERS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "details",
+    "div",
+    "dl",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hgroup",
+    "hr",
+    "main",
+    "menu",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "ul",
+];
+
+struct Tag<'a> {
+    name: String,
+    end: bool,
+    attrs: Vec<(&'a str, Option<&'a str>)>,
+    self_closing: bool,
+    /// Length of the tag in the source
+    len: usize,
+}
+
+/// Parses the tag at the start of `s`, which must start with `<`.
+fn parse_tag(s: &str) -> Option<Tag<'_>> {
+    let bytes = s.as_bytes();
+    let mut i = 1;
+    let end = bytes.get(i) == Some(&b'/');
+    if end {
+        i += 1;
+    }
+    let name_start = i;
+    if !bytes.get(i)?.is_ascii_alphabetic() {
+        return None;
+    }
+    while bytes
+        .get(i)
+        .is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'-' || *b == b':')
+    {
+        i += 1;
+    }
+    let name = s[name_start..i].to_ascii_lowercase();
+
+    let mut attrs = Vec::new();
+    let mut self_closing = false;
+    loop {
+        while bytes.get(i)?.is_ascii_whitespace() {
+            i += 1;
+        }
+        match bytes[i] {
+            b'>' => break,
+            b'/' if bytes.get(i + 1) == Some(&b'>') => {
+                self_closing = true;
+                i += 1;
+                break;
+            }
+            b'/' => {
+                i += 1;
+                continue;
+            }
+            _ => (),
+        }
+        let attr_start = i;
+        while bytes
+            .get(i)
+            .is_some_and(|b| !b.is_ascii_whitespace() && !matches!(b, b'=' | b'>' | b'/'))
+        {
+            i += 1;
+        }
+        let attr = &s[attr_start..i];
+        while bytes.get(i)?.is_ascii_whitespace() {
+            i += 1;
+        }
+        if bytes[i] != b'=' {
+            if attr.is_empty() {
+                // A stray character, such as `"`, that is not part of any attribute
+                i += 1;
+                continue;
+            }
+            attrs.push((attr, None));
+            continue;
+        }
+        i += 1;
+        while bytes.get(i)?.is_ascii_whitespace() {
+            i += 1;
+        }
+        let value = match bytes.get(i)? {
+            quote @ (b'"' | b'\'') => {
+                let len = s[i + 1..].find(*quote as char)?;
+                let value = &s[i + 1..i + 1 + len];
+                i += len + 2;
+                value
+            }
+            _ => {
+                let value_start = i;
+                while bytes
+                    .get(i)
+                    .is_some_and(|b| !b.is_ascii_whitespace() && *b != b'>')
+                {
+                    i += 1;
+                }
+                &s[value_start..i]
+            }
+        };
+        attrs.push((attr, Some(value)));
+    }
+    Some(Tag {
+        name,
+        end,
+        attrs,
+        self_closing,
+        len: i + 1,
+    })
+}
+
+fn write_tag(out: &mut String, tag: &Tag) {
+    out.push('<');
+    if tag.end {
+        out.push('/');
+    }
+    out.push_str(&tag.name);
+    // Void elements need no `/>`, but foreign elements such as SVG do
+    let self_closing = tag.self_closing && !VOIDS.contains(&tag.name.as_str());
+    for (i, (name, value)) in tag.attrs.iter().enumerate() {
+        out.push(' ');
+        out.push_str(name);
+        let Some(value) = value else { continue };
+        out.push('=');
+        // An unquoted value would swallow the `/` of `/>`
+        let before_slash = self_closing && i + 1 == tag.attrs.len();
+        let unquoted = !value.is_empty()
+            && !before_slash
+            && !value
+                .chars()
+                .any(|c| c.is_ascii_whitespace() || "\"'=<>`".contains(c));
+        if unquoted {
+            out.push_str(value);
+        } else if value.contains('"') {
+            out.push('\'');
+            out.push_str(value);
+            out.push('\'');
+        } else {
+            out.push('"');
+            out.push_str(value);
+            out.push('"');
+        }
+    }
+    if self_closing {
+        out.push('/');
+    }
+    out.push('>');
+}
+
+/// Name of the tag `s` starts with, and whether it is an end tag.
+fn peek_tag(s: &str) -> Option<(String, bool)> {
+    if !s.starts_with('<') || s.starts_with("<!") {
+        return None;
+    }
+    let end = s[1..].starts_with('/');
+    let name: String = s[1 + end as usize..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect();
+    (!name.is_empty()).then(|| (name.to_ascii_lowercase(), end))
+}
+
+/// Whether the end tag of `name` may be left out when `rest` follows it.
+fn can_omit_end_tag(name: &str, rest: &str) -> bool {
+    let next = rest.trim_start();
+    let Some((next_name, next_end)) = peek_tag(next) else {
+        return false;
+    };
+    // Whitespace left in between would end up inside the element
+    if next.len() != rest.len() && !BLOCKS.contains(&next_name.as_str()) {
+        return false;
+    }
+    let next_is = |names: &[&str]| !next_end && names.contains(&next_name.as_str());
+    match name {
+        "li" => next_is(&["li"]) || next_end,
+        "dt" => next_is(&["dt", "dd"]),
+        "dd" => next_is(&["dt", "dd"]) || next_end,
+        "p" => {
+            next_is(P_CLOSERS)
+                || next_end
+                    && !["a", "audio", "del", "ins", "map", "noscript", "video"]
+                        .contains(&next_name.as_str())
+        }
+        "td" | "th" => next_is(&["td", "th"]) || next_end,
+        "tr" => next_is(&["tr"]) || next_end,
+        "option" => next_is(&["option", "optgroup"]) || next_end,
+        "thead" => next_is(&["tbody", "tfoot"]),
+        "tbody" => next_is(&["tbody", "tfoot"]) || next_end,
+        "tfoot" => next_end,
+        _ => false,
+    }
+}
+
+/// Minifies an HTML document.
+pub fn html(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    // Whether the last tag written is a block, making the whitespace after it insignificant
+    let mut after_block = true;
+
+    while !rest.is_empty() {
+        if rest.starts_with("<!--") {
+            let len = rest.find("-->").map_or(rest.len(), |i| i + 3);
+            // Conditional comments are markup for old browsers
+            if rest.starts_with("<!--[if") {
+                out.push_str(&rest[..len]);
+            }
+            rest = &rest[len..];
+            continue;
+        }
+        if rest.starts_with("<!") {
+            let len = rest.find('>').map_or(rest.len(), |i| i + 1);
+            out.push_str(&rest[..len]);
+            rest = &rest[len..];
+            after_block = true;
+            continue;
+        }
+        if rest.starts_with('<')
+            && let Some(tag) = parse_tag(rest)
+        {
+            rest = &rest[tag.len..];
+            after_block = BLOCKS.contains(&tag.name.as_str());
+            if tag.end {
+                if !can_omit_end_tag(&tag.name, rest) {
+                    write_tag(&mut out, &tag);
+                }
+                continue;
+            }
+            write_tag(&mut out, &tag);
+            if tag.self_closing
+                || !["pre", "textarea", "script", "style"].contains(&tag.name.as_str())
+            {
+                continue;
+            }
+            // Raw text, up to the end tag
+            let close = format!("</{}", tag.name);
+            let len = find_ignore_ascii_case(rest, &close).unwrap_or(rest.len());
+            let content = &rest[..len];
+            match tag.name.as_str() {
+                "style" => out.push_str(&css(content)),
+                "script" => out.push_str(&script(&tag, content)),
+                _ => out.push_str(content),
+            }
+            rest = &rest[len..];
+            continue;
+        }
+
+        // Text, up to the next tag or comment
+        let first = rest.chars().next().map_or(1, char::len_utf8);
+        let len = rest[first..].find('<').map_or(rest.len(), |i| i + first);
+        let text = &rest[..len];
+        rest = &rest[len..];
+        let before_block = peek_tag(rest).is_some_and(|(name, _)| BLOCKS.contains(&name.as_str()));
+        let mut collapsed = String::with_capacity(text.len());
+        let mut in_space = false;
+        for c in text.chars() {
+            if c.is_ascii_whitespace() {
+                in_space = true;
+                continue;
+            }
+            if in_space && !(collapsed.is_empty() && after_block) {
+                collapsed.push(' ');
+            }
+            in_space = false;
+            collapsed.push(c);
+        }
+        if in_space && !before_block && !(collapsed.is_empty() && after_block) {
+            collapsed.push(' ');
+        }
+        out.push_str(&collapsed);
+        if !collapsed.is_empty() {
+            after_block = false;
+        }
+    }
+    out
+}
+
+/// Offset of the first occurrence of the ASCII `needle` in `s`, ignoring case.
+fn find_ignore_ascii_case(s: &str, needle: &str) -> Option<usize> {
+    s.as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// Minifies a script, if it is JavaScript or JSON.
+fn script(tag: &Tag, content: &str) -> String {
+    let kind = tag
+        .attrs
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("type"))
+        .and_then(|(_, value)| *value)
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    match kind.as_str() {
+        "" | "module" | "text/javascript" | "application/javascript" => js(content),
+        "importmap" | "application/json" | "application/ld+json" => {
+            match serde_json::from_str::<serde_json::Value>(content) {
+                Ok(json) => json.to_string(),
+                Err(_) => content.to_string(),
+            }
+        }
+        _ => content.to_string(),
+    }
+}
+
+/// Length of the string literal at the start of `s`.
+fn string_len(s: &str) -> usize {
+    let quote = s.chars().next().unwrap_or_default();
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == quote {
+            return i + 1;
+        }
+    }
+    s.len()
+}
+
+/// Length of the template literal at the start of `s`. Substitutions (`${...}`) are code, which
+/// may hold strings and templates of their own.
+fn template_len(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let mut i = 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'`' => return i + 1,
+            b'$' if bytes.get(i + 1) == Some(&b'{') => {
+                i += 2;
+                let mut depth = 1;
+                while i < bytes.len() && depth > 0 {
+                    match bytes[i] {
+                        b'{' => depth += 1,
+                        b'}' => depth -= 1,
+                        b'"' | b'\'' => i += string_len(&s[i..]) - 1,
+                        b'`' => i += template_len(&s[i..]) - 1,
+                        _ => (),
+                    }
+                    i += 1;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    s.len()
+}
+
+/// Drops comments and collapses whitespace in a stylesheet.
+pub fn css(input: &str) -> String {
+    const TIGHT: &str = "{};,>";
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    let mut space = false;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("/*") {
+            rest = rest[2..].find("*/").map_or("", |i| &rest[i + 4..]);
+            space = true;
+            continue;
+        }
+        if c.is_ascii_whitespace() {
+            space = true;
+            rest = &rest[1..];
+            continue;
+        }
+        if c == '}' && out.ends_with(';') {
+            out.pop();
+        }
+        if space
+            && !out.is_empty()
+            && !out.ends_with(|p: char| TIGHT.contains(p) || p == ':')
+            && !TIGHT.contains(c)
+        {
+            out.push(' ');
+        }
+        space = false;
+        if c == '"' || c == '\'' {
+            let len = string_len(rest);
+            out.push_str(&rest[..len]);
+            rest = &rest[len..];
+            continue;
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+/// Drops comments and collapses whitespace in a script. Line breaks are kept, as they may end
+/// statements.
+pub fn js(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    // `None` when no whitespace is pending, else whether it holds a line break
+    let mut space: Option<bool> = None;
+    // Whether each open parenthesis holds the condition of an `if` or a loop, after which a `/`
+    // starts a regular expression rather than a division
+    let mut parens: Vec<bool> = Vec::new();
+    let mut after_condition = false;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("//") {
+            rest = rest.find('\n').map_or("", |i| &rest[i..]);
+            continue;
+        }
+        if rest.starts_with("/*") {
+            let comment_len = rest[2..].find("*/").map_or(rest.len(), |i| i + 4);
+            let newline = rest[..comment_len].contains('\n');
+            space = Some(space.unwrap_or(false) || newline);
+            rest = &rest[comment_len..];
+            continue;
+        }
+        if c.is_ascii_whitespace() {
+            space = Some(space.unwrap_or(false) || c == '\n');
+            rest = &rest[1..];
+            continue;
+        }
+        if let Some(newline) = space.take()
+            && !out.is_empty()
+        {
+            out.push(if newline { '\n' } else { ' ' });
+        }
+        let len = match c {
+            '"' | '\'' => string_len(rest),
+            '`' => template_len(rest),
+            '/' if after_condition || starts_regex(&out) => regex_len(rest),
+            _ => c.len_utf8(),
+        };
+        after_condition = false;
+        match c {
+            '(' => parens.push(ends_with_keyword(&out, &["if", "while", "for", "with"])),
+            ')' => after_condition = parens.pop().unwrap_or(false),
+            _ => (),
+        }
+        out.push_str(&rest[..len]);
+        rest = &rest[len..];
+    }
+    out
+}
+
+/// Whether a `/` after `code` starts a regular expression rather than being a division.
+fn starts_regex(code: &str) -> bool {
+    let code = code.trim_end();
+    match code.chars().last() {
+        None => true,
+        Some(c) if "(,=:[!&|?{};+-*%<>~^".contains(c) => true,
+        _ => ends_with_keyword(
+            code,
+            &["return", "typeof", "case", "do", "else", "in", "of"],
+        ),
+    }
+}
+
+/// Whether `code` ends with one of `keywords`, as a whole word.
+fn ends_with_keyword(code: &str, keywords: &[&str]) -> bool {
+    let code = code.trim_end();
+    keywords.iter().any(|keyword| {
+        code.strip_suffix(keyword).is_some_and(|before| {
+            !before.ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == '$')
+        })
+    })
+}
+
+/// Length of the regular expression literal at the start of `s`.
+fn regex_len(s: &str) -> usize {
+    let mut escaped = false;
+    let mut in_class = false;
+    for (i, c) in s.char_indices().skip(1) {
+        match c {
+            '\n' => return i,
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '/' if !in_class => {
+                let flags = s[i + 1..]
+                    .find(|c: char| !c.is_ascii_alphabetic())
+                    .unwrap_or(s.len() - i - 1);
+                return i + 1 + flags;
+            }
+            _ => (),
+        }
+    }
+    s.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html() {
+        let page = r#"<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="utf-8">
+    <title> A  page </title>
+    <!-- a comment -->
+  </head>
+  <body>
+    <p class="intro" title="two words">Some   <em>emphasis</em> and <a href="/a/">a link</a>.</p>
+    <ul>
+      <li>One</li>
+      <li>Two</li>
+    </ul>
+    <pre><code>keep
+   this</code></pre>
+    <svg><path d="M0 0"/></svg>
+  </body>
+</html>"#;
+        assert_eq!(
+            html(page),
+            "<!DOCTYPE html><html><head><meta charset=utf-8><title>A page</title></head>\
+             <body><p class=intro title=\"two words\">Some <em>emphasis</em> and \
+             <a href=/a/>a link</a>.<ul><li>One<li>Two</ul><pre><code>keep\n   this</code></pre>\
+             <svg><path d=\"M0 0\"/></svg></body></html>"
+        );
+        assert_eq!(html("<p>héllo  wörld</p>"), "<p>héllo wörld</p>");
+        assert_eq!(html("<circle r='4' />"), "<circle r=\"4\"/>");
+    }
+
+    #[test]
+    fn test_end_tags() {
+        // `</p>` is needed before inline content and inside links
+        assert_eq!(html("<div><p>a</p>b</div>"), "<div><p>a</p>b</div>");
+        assert_eq!(html("<a href=x><p>a</p></a>"), "<a href=x><p>a</p></a>");
+        assert_eq!(
+            html("<table><tr><td>1</td><td>2</td></tr></table>"),
+            "<table><tr><td>1<td>2</table>"
+        );
+    }
+
+    #[test]
+    fn test_adversarial() {
+        // `</p>` is left out before the end of any element but those that may hold it inline
+        assert_eq!(
+            html("<section><p>a</p></section><blockquote><p>b</p>\n</blockquote>"),
+            "<section><p>a</section><blockquote><p>b</blockquote>"
+        );
+        assert_eq!(
+            html("<video><p>a</p></video><del><p>b</p></del>"),
+            "<video><p>a</p></video><del><p>b</p></del>"
+        );
+        // Raw text ends at its end tag whatever its case, and not before
+        assert_eq!(
+            html("<PRE>a  </Pre>b <textarea> x </TEXTAREA>"),
+            "<pre>a  </pre>b <textarea> x </textarea>"
+        );
+
+        // Backticks and comment markers inside substitutions stay part of the template
+        assert_eq!(
+            js("let s = `a ${`b  // ${\"}\"}`}  c`;  // done\n"),
+            "let s = `a ${`b  // ${\"}\"}`}  c`;"
+        );
+        // After the condition of an `if`, a `/` starts a regular expression
+        assert_eq!(
+            js("if (a) /  \"x/.test(s);\nlet d = (a) / 2 / b;"),
+            "if (a) /  \"x/.test(s);\nlet d = (a) / 2 / b;"
+        );
+        assert_eq!(js("while (f(x)) /'/g.exec(s)"), "while (f(x)) /'/g.exec(s)");
+    }
+
+    #[test]
+    fn test_css_and_js() {
+        assert_eq!(
+            css("a:hover , p > em {\n  color : red ; /* note */ content: \"a  b\";\n}\n"),
+            "a:hover,p>em{color :red;content:\"a  b\"}"
+        );
+        assert_eq!(
+            js(
+                "// setup\nlet a = 1;   /* x */\nlet s = \"//  not a comment\";\nlet r = /\\/\\//g;\n"
+            ),
+            "let a = 1;\nlet s = \"//  not a comment\";\nlet r = /\\/\\//g;"
+        );
+        assert_eq!(
+            html("<script type=\"application/ld+json\">\n{ \"a\": 1 }\n</script>"),
+            "<script type=application/ld+json>{\"a\":1}</script>"
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! Splitting listing pages into numbered pages (`/blog/`, `/blog/page/2/`, ...).
+
+use crate::frontmatter::Date;
+use crate::site::PageMeta;
+
+/// A page as shown in listings.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct PageRef {
+    pub title: String,
+    pub url: String,
+    pub date: Option<Date>,
+}
+
+impl From<&PageMeta> for PageRef {
+    fn from(meta: &PageMeta) -> Self {
+        Self {
+            title: meta.title().to_string(),
+            url: meta.url.clone(),
+            date: meta.front_matter.date,
+        }
+    }
+}
+
+/// Sorts pages newest first, undated pages last, and then by title.
+pub fn sort_pages(pages: &mut [PageRef]) {
+    pages.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.title.cmp(&b.title)));
+}
+
+/// One page of a listing, as exposed to layouts.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Pager {
+    /// 1-based index of this page
+    pub index: usize,
+    pub total: usize,
+    pub url: String,
+    pub pages: Vec<PageRef>,
+    pub first: String,
+    pub last: String,
+    pub prev: Option<String>,
+    pub next: Option<String>,
+}
+
+/// URL of the `n`th (1-based) page of a listing rooted at `base`. The first page is the root itself.
+pub fn pager_url(base: &str, n: usize) -> String {
+    if n <= 1 {
+        base.to_string()
+    } else {
+        format!("{}page/{}/", base, n)
+    }
+}
+
+/// Splits `pages` into pagers of `per_page` items. Without a limit, or with an empty listing,
+/// there is a single pager.
+pub fn paginate(base: &str, pages: Vec<PageRef>, per_page: Option<usize>) -> Vec<Pager> {
+    let chunks: Vec<Vec<PageRef>> = match per_page {
+        Some(n) if n > 0 && !pages.is_empty() => pages.chunks(n).map(|c| c.to_vec()).collect(),
+        _ => vec![pages],
+    };
+
+    let total = chunks.len();
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, pages)| {
+            let index = i + 1;
+            Pager {
+                index,
+                total,
+                url: pager_url(base, index),
+                pages,
+                first: pager_url(base, 1),
+                last: pager_url(base, total),
+                prev: (index > 1).then(|| pager_url(base, index - 1)),
+                next: (index < total).then(|| pager_url(base, index + 1)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pages(n: usize) -> Vec<PageRef> {
+        (0..n)
+            .map(|i| PageRef {
+                title: i.to_string(),
+                url: format!("/blog/{}/", i),
+                date: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_paginate() {
+        let pagers = paginate("/blog/", pages(5), Some(2));
+        assert_eq!(pagers.len(), 3);
+        assert_eq!(pagers[0].url, "/blog/");
+        assert_eq!(pagers[0].prev, None);
+        assert_eq!(pagers[0].next.as_deref(), Some("/blog/page/2/"));
+        assert_eq!(pagers[1].prev.as_deref(), Some("/blog/"));
+        assert_eq!(pagers[2].pages.len(), 1);
+        assert_eq!(pagers[2].next, None);
+        assert_eq!(pagers[2].last, "/blog/page/3/");
+
+        assert_eq!(paginate("/blog/", pages(5), None).len(), 1);
+        assert_eq!(paginate("/blog/", pages(0), Some(2)).len(), 1);
+    }
+}
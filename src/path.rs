@@ -1,5 +1,9 @@
 use crate::internal_prelude::*;
+use crate::vfs::Vfs;
 use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt,
     ops::Deref,
     path::{Component, Path, PathBuf},
 };
@@ -9,6 +13,68 @@ pub enum SourcePath {
     Absolute(String),
 }
 
+/// Why a path cannot be made into a [`SrcPath`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SrcPathError {
+    NotUtf8(PathBuf),
+    NoFileName(PathBuf),
+    /// The path must be absolute if the anchor is empty, and relative otherwise
+    InvalidAnchor {
+        path: PathBuf,
+        anchor: PathBuf,
+    },
+    /// The relative path leads outside of its anchor, through `..`
+    EscapesRoot {
+        path: PathBuf,
+        anchor: PathBuf,
+    },
+    /// The path, or the file a symlink on it points to, is outside of the [`Sandbox`]
+    OutsideSandbox {
+        path: PathBuf,
+        target: Option<PathBuf>,
+    },
+}
+
+impl fmt::Display for SrcPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SrcPathError::NotUtf8(path) => write!(f, "Path {} is not valid UTF-8", path.display()),
+            SrcPathError::NoFileName(path) => write!(f, "Path {} has no file name", path.display()),
+            SrcPathError::InvalidAnchor { path, anchor } if path.is_absolute() => write!(
+                f,
+                "Path {} is absolute, expected a path relative to {}",
+                path.display(),
+                anchor.display()
+            ),
+            SrcPathError::InvalidAnchor { path, .. } => write!(
+                f,
+                "Path {} is relative, but there is nothing to resolve it against",
+                path.display()
+            ),
+            SrcPathError::EscapesRoot { path, anchor } => write!(
+                f,
+                "Path {} points outside of {}",
+                path.display(),
+                anchor.display()
+            ),
+            SrcPathError::OutsideSandbox { path, target: None } => {
+                write!(f, "Path {} is outside of the site", path.display())
+            }
+            SrcPathError::OutsideSandbox {
+                path,
+                target: Some(target),
+            } => write!(
+                f,
+                "Path {} links to {}, which is outside of the site",
+                path.display(),
+                target.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SrcPathError {}
+
 #[derive(Debug, Eq, PartialEq, Clone, Hash, serde::Serialize, serde::Deserialize)]
 pub struct SrcPath {
     path: PathBuf,
@@ -17,54 +83,155 @@ pub struct SrcPath {
     ext_i: usize,
 }
 impl SrcPath {
+    /// Like [`SrcPath::try_from_relaxed_path`], for paths known to be valid.
+    ///
+    /// # Panics
+    ///
+    /// If the path is invalid.
     pub fn from_relaxed_path(path: impl AsRef<Path>, anchor: impl AsRef<Path>) -> Self {
-        let anchor_i = if path.as_ref().is_absolute() && anchor.as_ref().as_os_str().is_empty() {
-            0
-        } else if path.as_ref().is_relative() && !anchor.as_ref().as_os_str().is_empty() {
-            anchor.as_ref().to_str().unwrap().len()
-        } else {
-            panic!(
-                "Invalid path and anchor combination: path must be absolute if anchor is empty, and relative if anchor is non-empty"
-            );
+        Self::try_from_relaxed_path(path, anchor).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Builds a path from an absolute path and an empty anchor, or from a path relative to a
+    /// non-empty anchor. Relative paths may not leave their anchor.
+    pub fn try_from_relaxed_path(
+        path: impl AsRef<Path>,
+        anchor: impl AsRef<Path>,
+    ) -> Result<Self, SrcPathError> {
+        let (path, anchor) = (path.as_ref(), anchor.as_ref());
+        let invalid_anchor = || SrcPathError::InvalidAnchor {
+            path: path.to_path_buf(),
+            anchor: anchor.to_path_buf(),
         };
+        if anchor.as_os_str().is_empty() != path.is_absolute() {
+            return Err(invalid_anchor());
+        }
 
-        // remove any possible "./" and join
-        let path = if anchor_i > 0 {
-            let joined = anchor.as_ref().join(path.as_ref());
-            normalize_path(joined)
+        // remove any possible "./" and join. The anchor ends where its normalized form does,
+        // which may be shorter than the anchor as written.
+        let (normalized, anchor_i) = if !anchor.as_os_str().is_empty() {
+            let joined = normalize_path(anchor.join(path));
+            let root = normalize_path(anchor);
+            let anchor_i = root
+                .to_str()
+                .ok_or_else(|| SrcPathError::NotUtf8(anchor.to_path_buf()))?
+                .len();
+            if anchor_i == 0 {
+                return Err(invalid_anchor());
+            }
+            if !joined.starts_with(&root) {
+                return Err(SrcPathError::EscapesRoot {
+                    path: path.to_path_buf(),
+                    anchor: anchor.to_path_buf(),
+                });
+            }
+            if joined == root {
+                // The anchor itself, the file name would be part of it
+                return Err(SrcPathError::NoFileName(path.to_path_buf()));
+            }
+            (joined, anchor_i)
         } else {
-            normalize_path(path)
+            (normalize_path(path), 0)
         };
 
-        let filename_i = path
+        let full = normalized
+            .to_str()
+            .ok_or_else(|| SrcPathError::NotUtf8(path.to_path_buf()))?;
+        let filename = normalized
             .file_name()
             .and_then(|os_str| os_str.to_str())
-            .map(|s| {
-                let full_path_str = path.to_str().unwrap();
-                full_path_str.len() - s.len()
-            })
-            .expect("Failed to get filename index");
-        let ext_index = path
-            .extension()
-            .and_then(|os_str| os_str.to_str())
-            .map(|s| {
-                let full_path_str = path.to_str().unwrap();
-                full_path_str.len() - s.len() - 1 // -1 for the dot
-            })
-            .unwrap_or(path.to_str().unwrap().len()); // No extension case
+            .ok_or_else(|| SrcPathError::NoFileName(path.to_path_buf()))?;
+        let filename_i = full.len() - filename.len();
+        let ext_i = match normalized.extension().and_then(|os_str| os_str.to_str()) {
+            Some(ext) => full.len() - ext.len() - 1, // -1 for the dot
+            None => full.len(),
+        };
 
-        SrcPath {
-            path,
+        Ok(SrcPath {
+            path: normalized,
             anchor_i,
             filename_i,
-            ext_i: ext_index,
-        }
+            ext_i,
+        })
     }
     /// Returns a string representation of the path up to the filename, which can be used as an
     /// anchor for resolving relative paths.
     pub fn as_anchor(&self) -> &str {
         &self.path.to_str().unwrap()[..self.filename_i]
     }
+    /// Returns the part of the path after the anchor it was constructed with, without a leading
+    /// separator. For anchorless paths this is the whole path.
+    pub fn rel_path(&self) -> &str {
+        let path = self.path.to_str().unwrap();
+        path.get(self.anchor_i..)
+            .unwrap_or(path)
+            .trim_start_matches('/')
+    }
+    /// Returns the anchor the path was constructed with, empty for anchorless paths.
+    pub fn anchor(&self) -> &str {
+        &self.path.to_str().unwrap()[..self.anchor_i]
+    }
+    /// Returns the directory part of [`SrcPath::rel_path`], with a trailing separator unless empty.
+    pub fn rel_dir(&self) -> &str {
+        let rel = self.rel_path();
+        &rel[..rel.len() - self.filename().len()]
+    }
+    /// Resolves a relative path against the directory of this path, keeping the same anchor.
+    ///
+    /// # Panics
+    ///
+    /// If the result is not a valid path, see [`SrcPath::try_sibling`].
+    pub fn sibling(&self, rel: impl AsRef<Path>) -> SrcPath {
+        self.try_sibling(rel).unwrap_or_else(|e| panic!("{}", e))
+    }
+    pub fn try_sibling(&self, rel: impl AsRef<Path>) -> Result<SrcPath, SrcPathError> {
+        let rel = rel.as_ref();
+        if !rel.is_relative() {
+            return Err(SrcPathError::InvalidAnchor {
+                path: rel.to_path_buf(),
+                anchor: PathBuf::from(self.as_anchor()),
+            });
+        }
+        if self.anchor_i == 0 {
+            SrcPath::try_from_relaxed_path(Path::new(self.as_anchor()).join(rel), "")
+        } else {
+            SrcPath::try_from_relaxed_path(Path::new(self.rel_dir()).join(rel), self.anchor())
+        }
+    }
+    /// Resolves a link written in this file. Relative links resolve against its directory.
+    /// Root-relative links (`/images/a.png`) resolve against the directory mounted at their
+    /// longest matching prefix, or else against this path's anchor. Either way the result stays
+    /// inside the directory it was resolved against.
+    pub fn try_resolve_link(
+        &self,
+        link: &str,
+        mounts: &BTreeMap<String, PathBuf>,
+    ) -> Result<SrcPath, SrcPathError> {
+        let Some(rooted) = link.strip_prefix('/') else {
+            return self.try_sibling(link);
+        };
+        let mount = mounts
+            .iter()
+            .filter(|(prefix, _)| {
+                let prefix = prefix.trim_end_matches('/');
+                link == prefix
+                    || link
+                        .strip_prefix(prefix)
+                        .is_some_and(|r| r.starts_with('/'))
+            })
+            .max_by_key(|(prefix, _)| prefix.len());
+        match mount {
+            Some((prefix, dir)) => {
+                let rest = link[prefix.trim_end_matches('/').len()..].trim_start_matches('/');
+                SrcPath::try_from_relaxed_path(rest, dir)
+            }
+            None if self.anchor_i == 0 => Err(SrcPathError::InvalidAnchor {
+                path: PathBuf::from(link),
+                anchor: PathBuf::new(),
+            }),
+            None => SrcPath::try_from_relaxed_path(rooted, self.anchor()),
+        }
+    }
     pub fn filename(&self) -> &str {
         &self.path.to_str().unwrap()[self.filename_i..]
     }
@@ -90,6 +257,130 @@ impl AsRef<Path> for SrcPath {
     }
 }
 
+/// Directories source files may be read from: the site root, and any directories allowed in the
+/// configuration. Paths are checked as written, relative ones against the current directory, and
+/// optionally where their symlinks point, as told by the [`Vfs`] files are read through.
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    /// `None` for no restriction
+    roots: Option<Vec<PathBuf>>,
+    /// Canonical roots, when symlinks are checked
+    real_roots: Option<Vec<PathBuf>>,
+}
+
+impl Sandbox {
+    /// A sandbox letting every file be read, which sites opt into with `sandbox.unrestricted`.
+    pub fn unrestricted() -> Self {
+        Self::default()
+    }
+
+    pub fn new(roots: impl IntoIterator<Item = PathBuf>, symlinks: bool, vfs: &dyn Vfs) -> Self {
+        let roots: Vec<PathBuf> = roots.into_iter().map(soft_cannonicalize_cwd).collect();
+        let real_roots = symlinks.then(|| {
+            roots
+                .iter()
+                .map(|root| vfs.canonicalize(root).unwrap_or_else(|_| root.clone()))
+                .collect()
+        });
+        Self {
+            roots: Some(roots),
+            real_roots,
+        }
+    }
+
+    pub fn check(&self, path: &Path, vfs: &dyn Vfs) -> Result<(), SrcPathError> {
+        let Some(roots) = &self.roots else {
+            return Ok(());
+        };
+        let path = soft_cannonicalize_cwd(path);
+        if !roots.iter().any(|root| path.starts_with(root)) {
+            return Err(SrcPathError::OutsideSandbox { path, target: None });
+        }
+        // A file that does not exist is reported when it is read
+        if let Some(real_roots) = &self.real_roots
+            && let Ok(target) = vfs.canonicalize(&path)
+            && !real_roots.iter().any(|root| target.starts_with(root))
+        {
+            return Err(SrcPathError::OutsideSandbox {
+                path,
+                target: Some(target),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A link destination from markdown, split into the file it points to and the query and fragment
+/// that follow it, as in `my%20photo.png?v=2#top`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkDest<'a> {
+    /// Percent-decoded path
+    pub path: Cow<'a, str>,
+    pub query: Option<&'a str>,
+    pub fragment: Option<&'a str>,
+}
+
+impl<'a> LinkDest<'a> {
+    pub fn parse(dest: &'a str) -> Self {
+        let (rest, fragment) = match dest.split_once('#') {
+            Some((rest, fragment)) => (rest, Some(fragment)),
+            None => (dest, None),
+        };
+        let (path, query) = match rest.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (rest, None),
+        };
+        LinkDest {
+            path: percent_decode(path),
+            query,
+            fragment,
+        }
+    }
+
+    /// The URL to write in the output: `url` with the original query and fragment reattached.
+    pub fn to_url(&self, url: &str) -> String {
+        let mut out = url.to_string();
+        if let Some(query) = self.query {
+            out.push('?');
+            out.push_str(query);
+        }
+        if let Some(fragment) = self.fragment {
+            out.push('#');
+            out.push_str(fragment);
+        }
+        out
+    }
+}
+
+/// Decodes `%XX` escapes. Malformed escapes are kept as is, and invalid UTF-8 is replaced.
+pub fn percent_decode(s: &str) -> Cow<'_, str> {
+    if !s.contains('%') {
+        return Cow::Borrowed(s);
+    }
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = |b: u8| (b as char).to_digit(16);
+        if bytes[i] == b'%'
+            && let (Some(hi), Some(lo)) = (
+                bytes.get(i + 1).copied().and_then(hex),
+                bytes.get(i + 2).copied().and_then(hex),
+            )
+        {
+            decoded.push((hi * 16 + lo) as u8);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    match String::from_utf8(decoded) {
+        Ok(decoded) => Cow::Owned(decoded),
+        Err(e) => Cow::Owned(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+    }
+}
+
 // https://github.com/rust-lang/cargo/blob/e490fd421fd85efd5cd31a4aacbd0bb42a567d3e/crates/cargo-util/src/paths.rs#L84
 // - Does not fail on invalid paths like std::fs::canonicalize
 // - Does not resolve symlinks
@@ -169,4 +460,156 @@ mod tests {
             assert_eq!(normalized, std::path::PathBuf::from(expected));
         }
     }
+
+    #[test]
+    fn test_sibling() {
+        use super::SrcPath;
+        let page = SrcPath::from_relaxed_path("blog/post.md", "/site/content");
+        assert_eq!(page.rel_dir(), "blog/");
+        let other = page.sibling("../about.md");
+        assert_eq!(other.rel_path(), "about.md");
+        assert_eq!(other.anchor(), "/site/content");
+        assert_eq!(&*other, std::path::Path::new("/site/content/about.md"));
+    }
+
+    #[test]
+    fn test_resolve_link() {
+        use super::SrcPath;
+        use std::collections::BTreeMap;
+        use std::path::{Path, PathBuf};
+        let page = SrcPath::from_relaxed_path("blog/post.md", "/site/content");
+        let mounts = BTreeMap::from([("/static".to_string(), PathBuf::from("/site/static"))]);
+
+        let resolve = |link| page.try_resolve_link(link, &mounts);
+        assert_eq!(
+            &*resolve("img.png").unwrap(),
+            Path::new("/site/content/blog/img.png")
+        );
+        assert_eq!(
+            &*resolve("/images/logo.png").unwrap(),
+            Path::new("/site/content/images/logo.png")
+        );
+        assert_eq!(
+            &*resolve("/static/logo.png").unwrap(),
+            Path::new("/site/static/logo.png")
+        );
+        assert_eq!(
+            &*resolve("/statics/logo.png").unwrap(),
+            Path::new("/site/content/statics/logo.png")
+        );
+        assert!(resolve("/../../etc/passwd").is_err());
+        assert!(resolve("/static/../../etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_sandbox() {
+        use super::{Sandbox, SrcPathError};
+        use crate::vfs::{DiskVfs, MemoryVfs};
+        use std::path::Path;
+        let site = std::env::temp_dir().join(format!("aaska-sandbox-{}", std::process::id()));
+        let outside = site.with_extension("outside");
+        std::fs::create_dir_all(&site).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("secret"), "").unwrap();
+
+        let sandbox = Sandbox::new([site.clone()], true, &DiskVfs);
+        assert!(sandbox.check(&site.join("a/b.png"), &DiskVfs).is_ok());
+        assert!(matches!(
+            sandbox.check(&site.join("../../etc/passwd"), &DiskVfs),
+            Err(SrcPathError::OutsideSandbox { target: None, .. })
+        ));
+        assert!(
+            Sandbox::unrestricted()
+                .check(Path::new("/etc/passwd"), &DiskVfs)
+                .is_ok()
+        );
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(outside.join("secret"), site.join("link")).unwrap();
+            assert!(matches!(
+                sandbox.check(&site.join("link"), &DiskVfs),
+                Err(SrcPathError::OutsideSandbox {
+                    target: Some(_),
+                    ..
+                })
+            ));
+            // Files in memory are where their paths say, whatever is on disk
+            let vfs = MemoryVfs::new();
+            vfs.insert(site.join("link"), "");
+            assert!(sandbox.check(&site.join("link"), &vfs).is_ok());
+            assert!(
+                Sandbox::new([site.clone()], false, &DiskVfs)
+                    .check(&site.join("link"), &DiskVfs)
+                    .is_ok()
+            );
+        }
+
+        std::fs::remove_dir_all(&site).unwrap();
+        std::fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn test_link_dest() {
+        use super::LinkDest;
+        let dest = LinkDest::parse("my%20photo.png?v=2#top");
+        assert_eq!(dest.path, "my photo.png");
+        assert_eq!(dest.query, Some("v=2"));
+        assert_eq!(dest.fragment, Some("top"));
+        assert_eq!(dest.to_url("a1b2.png"), "a1b2.png?v=2#top");
+
+        let dest = LinkDest::parse("page.md#intro");
+        assert_eq!((dest.path.as_ref(), dest.query), ("page.md", None));
+        assert_eq!(dest.to_url("/page/"), "/page/#intro");
+
+        assert_eq!(LinkDest::parse("100%.png").path, "100%.png");
+        assert_eq!(LinkDest::parse("caf%C3%A9.md").path, "café.md");
+    }
+
+    #[test]
+    fn test_try_from_relaxed_path() {
+        use super::{SrcPath, SrcPathError};
+        use std::path::PathBuf;
+        let page = SrcPath::from_relaxed_path("blog/post.md", "/site/content");
+
+        assert!(matches!(
+            SrcPath::try_from_relaxed_path("/abs.png", "/site/content"),
+            Err(SrcPathError::InvalidAnchor { .. })
+        ));
+        assert!(matches!(
+            SrcPath::try_from_relaxed_path("rel.png", ""),
+            Err(SrcPathError::InvalidAnchor { .. })
+        ));
+        assert!(matches!(
+            page.try_sibling("/abs.png"),
+            Err(SrcPathError::InvalidAnchor { .. })
+        ));
+        assert_eq!(
+            page.try_sibling("../../secret.md"),
+            Err(SrcPathError::EscapesRoot {
+                path: PathBuf::from("blog/../../secret.md"),
+                anchor: PathBuf::from("/site/content"),
+            })
+        );
+        assert!(matches!(
+            page.try_sibling(".."),
+            Err(SrcPathError::NoFileName(_))
+        ));
+        assert!(page.try_sibling("../img/a.png").is_ok());
+    }
+
+    #[test]
+    fn test_anchor_normalized() {
+        use super::{LinkDest, SrcPath};
+        use std::collections::BTreeMap;
+        let page = SrcPath::from_relaxed_path("été/post.md", "/site/./contenu-é/");
+        assert_eq!(page.anchor(), "/site/contenu-é");
+        assert_eq!(page.rel_path(), "été/post.md");
+
+        let dest = LinkDest::parse("images/%C3%A9t%C3%A9.png#%C3%A9t%C3%A9");
+        let asset = page.try_resolve_link(&dest.path, &BTreeMap::new()).unwrap();
+        assert_eq!(asset.anchor(), "/site/contenu-é");
+        assert_eq!(asset.rel_path(), "été/images/été.png");
+        assert_eq!(asset.rel_dir(), "été/images/");
+    }
 }
@@ -0,0 +1,209 @@
+//! Static full-text search index.
+//!
+//! The index is written as `search/docs.json`, listing the title, headings and URL of every page
+//! and the shards, plus one shard per token prefix (`search/shards/ru.json` holds `rust`, `run`,
+//! ...) mapping tokens to the URLs of the pages containing them. A client only downloads the shards
+//! for the words it looks up, and for a word shorter than a prefix, every shard starting with it.
+//!
+//! Tokenizing a page is a query of its own, so a rebuild only re-reads the pages that changed and
+//! then merges the cached entries into shards.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::OutputFile;
+use crate::db::{Db, SourceFile, render_chonk};
+use crate::site::{page_meta, published_pages};
+use picante::PicanteResult;
+
+/// Tokens shorter than this are not indexed.
+const MIN_TOKEN_LEN: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct SearchEntry {
+    pub url: String,
+    pub title: String,
+    pub headings: Vec<String>,
+    /// Unique lowercase words of the page, sorted
+    pub tokens: Vec<String>,
+}
+
+#[picante::tracked]
+pub async fn search_entry<DB: Db>(db: &DB, md_file: SourceFile) -> PicanteResult<SearchEntry> {
+    let _timing = crate::timings::query(db, "search_entry", md_file);
+    let meta = page_meta(db, md_file).await?;
+    let chonk = render_chonk(db, md_file).await?;
+
+    let mut text = html_to_text(&chonk.html);
+    text.push(' ');
+    text.push_str(meta.title());
+    let tokens: BTreeSet<String> = tokenize(&text).collect();
+
+    Ok(SearchEntry {
+        url: meta.url.clone(),
+        title: meta.title().to_string(),
+        headings: chonk.headings.into_iter().map(|h| h.text).collect(),
+        tokens: tokens.into_iter().collect(),
+    })
+}
+
+/// Writes the search index, or nothing if search is disabled.
+#[picante::tracked]
+pub async fn search_index<DB: Db>(db: &DB) -> PicanteResult<Vec<OutputFile>> {
+    let _timing = crate::timings::query(db, "search_index", ());
+    let config = &crate::config(db).site.search;
+    if !config.enabled {
+        return Ok(Vec::new());
+    }
+
+    let mut entries = Vec::new();
+    for page in published_pages(db).await? {
+        entries.push(search_entry(db, page.file).await?);
+    }
+    entries.sort_by(|a, b| a.url.cmp(&b.url));
+
+    let mut outputs = shard(&entries, config.prefix_len);
+    if config.client {
+        outputs.push(OutputFile::generated("search/search.js", CLIENT_JS));
+    }
+    Ok(outputs)
+}
+
+fn shard(entries: &[SearchEntry], prefix_len: usize) -> Vec<OutputFile> {
+    // prefix -> token -> urls
+    let mut shards: BTreeMap<String, BTreeMap<&str, Vec<&str>>> = BTreeMap::new();
+    for entry in entries {
+        for token in &entry.tokens {
+            shards
+                .entry(token_prefix(token, prefix_len))
+                .or_default()
+                .entry(token)
+                .or_default()
+                .push(&entry.url);
+        }
+    }
+
+    let docs = serde_json::json!({
+        "prefix_len": prefix_len.max(1),
+        "shards": shards.keys().collect::<Vec<_>>(),
+        "docs": entries
+            .iter()
+            .map(|e| serde_json::json!({ "url": e.url, "title": e.title, "headings": e.headings }))
+            .collect::<Vec<_>>(),
+    });
+    let mut outputs = vec![OutputFile::generated(
+        "search/docs.json",
+        serde_json::to_vec(&docs).expect("search docs are serializable"),
+    )];
+    for (prefix, tokens) in shards {
+        outputs.push(OutputFile::generated(
+            format!("search/shards/{}.json", prefix),
+            serde_json::to_vec(&tokens).expect("search shards are serializable"),
+        ));
+    }
+    outputs
+}
+
+fn token_prefix(token: &str, prefix_len: usize) -> String {
+    token.chars().take(prefix_len.max(1)).collect()
+}
+
+/// Splits text into lowercase words.
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= MIN_TOKEN_LEN)
+        .map(str::to_lowercase)
+}
+
+/// Strips tags from rendered HTML, keeping the text and decoding the entities the renderer emits.
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            c if !in_tag => text.push(c),
+            _ => (),
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Minimal client: `await aaskaSearch("some words")` resolves to the docs containing every word,
+/// matching word prefixes.
+const CLIENT_JS: &str = r#"(function () {
+  const base = document.currentScript.src.replace(/search\.js$/, "");
+  const cache = {};
+  const get = (f) => (cache[f] ??= fetch(base + f).then((r) => (r.ok ? r.json() : {})));
+  window.aaskaSearch = async function (query) {
+    const index = await get("docs.json");
+    const words = query.toLowerCase().split(/[^\p{L}\p{N}]+/u).filter((w) => w.length >= 2);
+    let result = null;
+    for (const word of words) {
+      const chars = [...word];
+      const prefixes = chars.length >= index.prefix_len
+        ? [chars.slice(0, index.prefix_len).join("")]
+        : index.shards.filter((p) => p.startsWith(word));
+      const urls = new Set();
+      for (const prefix of prefixes) {
+        const shard = await get("shards/" + prefix + ".json");
+        for (const token in shard) if (token.startsWith(word)) shard[token].forEach((u) => urls.add(u));
+      }
+      result = result ? new Set([...result].filter((u) => urls.has(u))) : urls;
+    }
+    return index.docs.filter((d) => result && result.has(d.url));
+  };
+})();
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_html() {
+        let text = html_to_text("<h1>Hello, World</h1>\n<p>Rust &amp; <code>a</code> web</p>");
+        let tokens: Vec<String> = tokenize(&text).collect();
+        assert_eq!(tokens, vec!["hello", "world", "rust", "web"]);
+    }
+
+    #[test]
+    fn test_shard() {
+        let entry = |url: &str, tokens: &[&str]| SearchEntry {
+            url: url.to_string(),
+            title: url.to_string(),
+            headings: vec![],
+            tokens: tokens.iter().map(|t| t.to_string()).collect(),
+        };
+        let entries = vec![
+            entry("/a/", &["rust", "web"]),
+            entry("/b/", &["rust", "run"]),
+        ];
+        let outputs = shard(&entries, 2);
+        let paths: Vec<_> = outputs.iter().map(|o| o.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "search/docs.json",
+                "search/shards/ru.json",
+                "search/shards/we.json"
+            ]
+        );
+        assert_eq!(
+            outputs[1].contents,
+            crate::OutputContents::Generated(br#"{"run":["/b/"],"rust":["/a/","/b/"]}"#.to_vec())
+        );
+
+        // Tokens starting with `docs` do not overwrite the list of documents
+        let outputs = shard(&[entry("/a/", &["docs"])], 4);
+        let paths: Vec<_> = outputs.iter().map(|o| o.path.as_str()).collect();
+        assert_eq!(paths, vec!["search/docs.json", "search/shards/docs.json"]);
+    }
+}
@@ -0,0 +1,505 @@
+//! Shortcodes: `{{< name key="value" >}}` and `{{< name >}}body{{< /name >}}` in markdown.
+//!
+//! Shortcodes are cut out of the markdown before parsing and replaced with placeholder HTML
+//! comments, which pulldown-cmark passes through untouched both inline and as blocks. Once the page
+//! is rendered, each placeholder is swapped for the shortcode's output.
+//!
+//! A shortcode is either registered through the library ([`Shortcode`]) or defined by the site as a
+//! template file in `templates/shortcodes/<name>.html`. Shortcodes cannot be nested, and shortcodes
+//! inside code (fenced or indented blocks, and inline code spans) are left as is. Writing
+//! `{{</* name */>}}` produces the literal `{{< name >}}`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::sync::Arc;
+
+use eyre::{Result, bail, eyre};
+use pulldown_cmark_escape::escape_html;
+
+use crate::db::SourceFile;
+
+/// Shortcode templates of the site, `templates/shortcodes/<name>.html`, by name. Set on every
+/// build, so that adding or removing a template re-renders the pages calling it. Without it, only
+/// registered shortcodes are expanded.
+#[picante::input]
+pub struct ShortcodeTemplates {
+    pub templates: Vec<(String, SourceFile)>,
+}
+
+/// A shortcode invocation found in a page.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct ShortcodeCall {
+    pub name: String,
+    /// Named arguments, positional arguments are keyed by their index (`"0"`, `"1"`, ...)
+    pub args: BTreeMap<String, String>,
+    /// Raw markdown between the opening and closing tag, for the block form
+    pub body: Option<String>,
+    /// Where the body starts in the original markdown
+    pub body_offset: usize,
+    /// Where the call is in the original markdown, closing tag included
+    pub range: Range<usize>,
+}
+
+impl ShortcodeCall {
+    pub fn arg(&self, name: &str) -> Option<&str> {
+        self.args.get(name).map(String::as_str)
+    }
+
+    pub fn placeholder(index: usize) -> String {
+        format!("<!--aaska-shortcode-{}-->", index)
+    }
+}
+
+/// A shortcode implemented in Rust.
+pub trait Shortcode: Send + Sync {
+    /// Returns the HTML the call expands to.
+    fn render(&self, call: &ShortcodeCall) -> Result<String>;
+}
+
+impl<F> Shortcode for F
+where
+    F: Fn(&ShortcodeCall) -> Result<String> + Send + Sync,
+{
+    fn render(&self, call: &ShortcodeCall) -> Result<String> {
+        self(call)
+    }
+}
+
+/// Shortcodes registered through the library. Template files in the site take precedence.
+#[derive(Clone, Default)]
+pub struct ShortcodeRegistry {
+    shortcodes: HashMap<String, Arc<dyn Shortcode>>,
+}
+
+impl ShortcodeRegistry {
+    /// A registry with the shortcodes aaska ships with.
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::default();
+        registry.register("youtube", youtube);
+        registry
+    }
+
+    pub fn register(&mut self, name: impl Into<String>, shortcode: impl Shortcode + 'static) {
+        self.shortcodes.insert(name.into(), Arc::new(shortcode));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Shortcode> {
+        self.shortcodes.get(name).map(|s| s.as_ref())
+    }
+}
+
+fn youtube(call: &ShortcodeCall) -> Result<String> {
+    let id = call
+        .arg("id")
+        .or_else(|| call.arg("0"))
+        .ok_or_else(|| eyre!("youtube shortcode requires an `id`"))?;
+    let mut html = String::from(
+        "<div class=\"shortcode-youtube\"><iframe src=\"https://www.youtube-nocookie.com/embed/",
+    );
+    escape_html(&mut html, id)?;
+    html.push_str(
+        "\" allow=\"encrypted-media; picture-in-picture\" allowfullscreen loading=\"lazy\"></iframe></div>",
+    );
+    Ok(html)
+}
+
+/// Markdown with its shortcodes replaced by placeholders.
+pub struct Extracted {
+    pub markdown: String,
+    /// Calls in placeholder order
+    pub calls: Vec<ShortcodeCall>,
+    /// `(offset in markdown, offset in the original)` after each rewrite
+    edits: Vec<(usize, usize)>,
+}
+
+impl Extracted {
+    /// Maps an offset in the rewritten markdown back to the original, so that diagnostics point
+    /// at what the author wrote.
+    pub fn original_offset(&self, offset: usize) -> usize {
+        match self.edits.iter().rev().find(|(out, _)| *out <= offset) {
+            Some((out, original)) => original + (offset - out),
+            None => offset,
+        }
+    }
+}
+
+/// Replaces every shortcode in `md` with a placeholder.
+pub fn extract(md: &str) -> Extracted {
+    let mut out = String::with_capacity(md.len());
+    let mut calls = Vec::new();
+    let mut edits = Vec::new();
+    let mut rest = md;
+    let mut fence: Option<&str> = None;
+    // Indented code blocks start after a blank line, and not inside list items where the indent
+    // belongs to the item
+    let mut in_indented_code = false;
+    let mut after_blank = true;
+    let mut in_list = false;
+
+    while !rest.is_empty() {
+        let line_end = rest.find('\n').map(|i| i + 1).unwrap_or(rest.len());
+        let line = &rest[..line_end];
+        let trimmed = line.trim_start();
+
+        // Leave fenced code blocks alone
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+            out.push_str(line);
+            rest = &rest[line_end..];
+            continue;
+        }
+        // What is left of a line after a shortcode is not the start of a block
+        let line_start = rest.len() == md.len() || md[..md.len() - rest.len()].ends_with('\n');
+        if line_start {
+            let blank = trimmed.is_empty();
+            let indent = indent_width(line);
+            let code = !blank && indent >= 4 && (in_indented_code || after_blank) && !in_list;
+            if !blank {
+                in_indented_code = code;
+                if indent < 4 && is_list_item(trimmed) {
+                    in_list = true;
+                } else if indent == 0 && after_blank {
+                    in_list = false;
+                }
+            }
+            after_blank = blank;
+            if code || trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                if !code {
+                    fence = Some(&trimmed[..3]);
+                }
+                out.push_str(line);
+                rest = &rest[line_end..];
+                continue;
+            }
+        }
+
+        // Leave inline code spans alone
+        let spans = code_spans(line);
+        let start = line
+            .match_indices("{{<")
+            .map(|(i, _)| i)
+            .find(|i| !spans.iter().any(|span| span.contains(i)));
+        let Some(start) = start else {
+            out.push_str(line);
+            rest = &rest[line_end..];
+            continue;
+        };
+        out.push_str(&line[..start]);
+        rest = &rest[start..];
+
+        // Escaped shortcode, written out literally
+        if let Some(escaped) = rest.strip_prefix("{{</*")
+            && let Some(end) = escaped.find("*/>}}")
+        {
+            out.push_str("{{<");
+            edits.push((out.len(), md.len() - escaped.len()));
+            out.push_str(&escaped[..end]);
+            out.push_str(">}}");
+            rest = &escaped[end + "*/>}}".len()..];
+            edits.push((out.len(), md.len() - rest.len()));
+            continue;
+        }
+
+        match parse_tag(rest) {
+            Some((tag, after)) if !tag.closing => {
+                let start = md.len() - rest.len();
+                let mut body_offset = md.len() - after.len();
+                let (body, after) = match find_closing(after, &tag.name) {
+                    Some((body_end, close_len)) => {
+                        let body = &after[..body_end];
+                        body_offset += body.len() - body.trim_start_matches('\n').len();
+                        (
+                            Some(body.trim_matches('\n').to_string()),
+                            &after[body_end + close_len..],
+                        )
+                    }
+                    None => (None, after),
+                };
+                rest = after;
+                out.push_str(&ShortcodeCall::placeholder(calls.len()));
+                edits.push((out.len(), md.len() - rest.len()));
+                calls.push(ShortcodeCall {
+                    name: tag.name,
+                    args: tag.args,
+                    body,
+                    body_offset,
+                    range: start..md.len() - rest.len(),
+                });
+            }
+            _ => {
+                // Not a shortcode after all, keep the braces and move on
+                out.push_str("{{<");
+                rest = &rest["{{<".len()..];
+            }
+        }
+    }
+    Extracted {
+        markdown: out,
+        calls,
+        edits,
+    }
+}
+
+/// Columns of whitespace `line` starts with, tabs stopping at multiples of 4.
+fn indent_width(line: &str) -> usize {
+    let mut width = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => width += 1,
+            '\t' => width += 4 - width % 4,
+            _ => break,
+        }
+    }
+    width
+}
+
+/// Whether a line, without its indent, starts a list item, as in `- item` or `1. item`.
+fn is_list_item(line: &str) -> bool {
+    let marker_end = match line.find(|c: char| !c.is_ascii_digit()) {
+        Some(0) if line.starts_with(['-', '*', '+']) => 1,
+        Some(digits) if digits > 0 && line[digits..].starts_with(['.', ')']) => digits + 1,
+        _ => return false,
+    };
+    line[marker_end..].starts_with([' ', '\t', '\n']) || line.len() == marker_end
+}
+
+/// Byte ranges of the inline code spans in `line`. A span ends at the next run of as many
+/// backticks as it starts with, and a run without a match is literal text.
+fn code_spans(line: &str) -> Vec<Range<usize>> {
+    let bytes = line.as_bytes();
+    let run_end = |mut i: usize| {
+        while bytes.get(i) == Some(&b'`') {
+            i += 1;
+        }
+        i
+    };
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'`' {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        i = run_end(i);
+        let len = i - start;
+        let mut j = i;
+        while j < bytes.len() {
+            if bytes[j] != b'`' {
+                j += 1;
+                continue;
+            }
+            let close = j;
+            j = run_end(j);
+            if j - close == len {
+                spans.push(start..j);
+                i = j;
+                break;
+            }
+        }
+    }
+    spans
+}
+
+struct Tag {
+    name: String,
+    args: BTreeMap<String, String>,
+    closing: bool,
+}
+
+/// Parses `{{< name args >}}` at the start of `s`, returning the tag and the rest of the input.
+fn parse_tag(s: &str) -> Option<(Tag, &str)> {
+    let inner_start = "{{<".len();
+    let end = s.find(">}}")?;
+    let inner = s[inner_start..end].trim();
+    let rest = &s[end + ">}}".len()..];
+
+    if let Some(name) = inner.strip_prefix('/') {
+        let tag = Tag {
+            name: name.trim().to_string(),
+            args: BTreeMap::new(),
+            closing: true,
+        };
+        return Some((tag, rest));
+    }
+
+    let name_end = inner.find(char::is_whitespace).unwrap_or(inner.len());
+    let name = &inner[..name_end];
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    {
+        return None;
+    }
+    let tag = Tag {
+        name: name.to_string(),
+        args: parse_args(&inner[name_end..])?,
+        closing: false,
+    };
+    Some((tag, rest))
+}
+
+/// Parses `key="value" key=value "positional"` style arguments.
+fn parse_args(s: &str) -> Option<BTreeMap<String, String>> {
+    let mut args = BTreeMap::new();
+    let mut chars = s.trim().chars().peekable();
+    let mut positional = 0;
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Some(args);
+        }
+
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '=' && *c != '"') {
+            key.push(c);
+        }
+        let value = if key.is_empty() || chars.next_if_eq(&'=').is_some() {
+            if chars.next_if_eq(&'"').is_some() {
+                let mut value = String::new();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => value.push(chars.next()?),
+                        c => value.push(c),
+                    }
+                }
+                value
+            } else {
+                let mut value = String::new();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    value.push(c);
+                }
+                value
+            }
+        } else {
+            // Bare word, a positional argument
+            std::mem::take(&mut key)
+        };
+
+        if key.is_empty() {
+            key = positional.to_string();
+            positional += 1;
+        }
+        args.insert(key, value);
+    }
+}
+
+/// Finds the closing tag for `name`, returning where the body ends and the length of the tag.
+/// Another opening tag with the same name means the shortcode has no body.
+fn find_closing(s: &str, name: &str) -> Option<(usize, usize)> {
+    let mut offset = 0;
+    while let Some(i) = s[offset..].find("{{<") {
+        let start = offset + i;
+        if let Some((tag, rest)) = parse_tag(&s[start..])
+            && tag.name == name
+        {
+            return tag.closing.then(|| (start, s.len() - start - rest.len()));
+        }
+        offset = start + "{{<".len();
+    }
+    None
+}
+
+/// Renders a shortcode template. `{{ key }}` is replaced with the escaped argument, `{{ body }}`
+/// with the rendered body, and `{{ include "file" }}` with the contents of a file loaded through
+/// `include`, so that the caller can track it.
+pub fn render_template(
+    template: &str,
+    call: &ShortcodeCall,
+    body_html: &str,
+    mut include: impl FnMut(&str) -> Result<String>,
+) -> Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find("}}") else {
+            bail!("Unclosed `{{{{` in shortcode template {}", call.name);
+        };
+        let expr = rest[start + 2..start + end].trim();
+        if expr == "body" {
+            out.push_str(body_html);
+        } else if let Some(file) = expr.strip_prefix("include") {
+            let file = file.trim().trim_matches('"');
+            out.push_str(&include(file)?);
+        } else if let Some(value) = call.arg(expr) {
+            escape_html(&mut out, value)?;
+        }
+        rest = &rest[start + end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract() {
+        let md = "Hi {{< youtube id=\"abc\" >}}!\n\n{{< note kind=warn >}}\n*careful*\n{{< /note >}}\n\n```\n{{< youtube x >}}\n```\n{{</* youtube x */>}}\n";
+        let extracted = extract(md);
+        let calls = &extracted.calls;
+        assert_eq!(
+            extracted.markdown,
+            "Hi <!--aaska-shortcode-0-->!\n\n<!--aaska-shortcode-1-->\n\n```\n{{< youtube x >}}\n```\n{{< youtube x >}}\n"
+        );
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].arg("id"), Some("abc"));
+        assert_eq!(calls[0].body, None);
+        assert_eq!(calls[1].name, "note");
+        assert_eq!(calls[1].arg("kind"), Some("warn"));
+        assert_eq!(calls[1].body.as_deref(), Some("*careful*"));
+
+        // Offsets after a placeholder point back at the original text
+        assert_eq!(&md[calls[0].range.clone()], "{{< youtube id=\"abc\" >}}");
+        let bang = extracted.markdown.find("!\n").unwrap();
+        assert_eq!(&md[extracted.original_offset(bang)..][..1], "!");
+        let last = extracted.markdown.rfind("youtube").unwrap();
+        assert_eq!(
+            extracted.original_offset(last),
+            md.rfind("youtube").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_extract_skips_code() {
+        let md = "`{{< a >}}` and ``x ` {{< b >}}`` {{< c >}}\n\n    {{< d >}}\n\n- item\n\n    {{< e >}}\n";
+        let extracted = extract(md);
+        let names: Vec<_> = extracted.calls.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["c", "e"]);
+        assert!(extracted.markdown.contains("`{{< a >}}`"));
+        assert!(extracted.markdown.contains("\n    {{< d >}}\n"));
+
+        // Text after a shortcode is not an indented line
+        assert_eq!(extract("{{< a >}}    {{< b >}}").calls.len(), 2);
+        assert_eq!(code_spans("a `b` ``c`` `d"), vec![2..5, 6..11]);
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse_args(r#" "first" key="a \"b\"" bare other=x "#).unwrap();
+        assert_eq!(args.get("0").map(String::as_str), Some("first"));
+        assert_eq!(args.get("1").map(String::as_str), Some("bare"));
+        assert_eq!(args.get("key").map(String::as_str), Some("a \"b\""));
+        assert_eq!(args.get("other").map(String::as_str), Some("x"));
+    }
+
+    #[test]
+    fn test_render_template() {
+        let calls = extract("{{< figure src=\"a<b\" >}}").calls;
+        let html = render_template(
+            "<figure>{{ src }}{{ include \"icon.svg\" }}{{ body }}</figure>",
+            &calls[0],
+            "<p>body</p>",
+            |file| Ok(format!("[{}]", file)),
+        )
+        .unwrap();
+        assert_eq!(html, "<figure>a&lt;b[icon.svg]<p>body</p></figure>");
+    }
+}
@@ -0,0 +1,255 @@
+//! Site-wide inputs and per-page metadata queries.
+
+use crate::backlinks::backlinks;
+use crate::db::{Db, SourceFile, SourceMtime, render_chonk, source_text};
+use crate::frontmatter::{Date, FrontMatter};
+use crate::path::SrcPath;
+use crate::{OutputContents, OutputFile};
+use picante::PicanteResult;
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+
+/// A page in the content tree. The handles are stable for a given path, so the page set only
+/// changes when pages are added or removed, not when they are edited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct PageInput {
+    pub file: SourceFile,
+    pub mtime: SourceMtime,
+}
+
+/// Every markdown page of the site, anchored at the content root.
+#[picante::input]
+pub struct ContentSet {
+    pub pages: Vec<PageInput>,
+}
+
+/// Which pages get published. `now` is refreshed on every build, so that a scheduled page shows
+/// up on the first rebuild after its date has passed.
+#[picante::input]
+pub struct PublishOptions {
+    /// Include pages marked `draft: true`
+    pub drafts: bool,
+    /// Include pages dated in the future
+    pub future: bool,
+    /// Current time, in seconds since the unix epoch
+    pub now: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct PageMeta {
+    pub src: SrcPath,
+    /// Site-relative URL of the rendered page, e.g. `/blog/post/`
+    pub url: String,
+    pub front_matter: FrontMatter,
+}
+
+impl PageMeta {
+    pub fn title(&self) -> &str {
+        self.front_matter
+            .title
+            .as_deref()
+            .unwrap_or_else(|| self.src.filename_no_ext())
+    }
+
+    /// Section index pages (`_index.md`) are rendered as listings of their directory rather than
+    /// as standalone pages.
+    pub fn is_section(&self) -> bool {
+        self.src.filename() == "_index.md"
+    }
+
+    /// Whether the page is a draft or scheduled after `now`, given what the build includes.
+    pub fn is_published(&self, drafts: bool, future: bool, now: i64) -> bool {
+        let is_draft = self.front_matter.draft;
+        let is_future = self.front_matter.date.is_some_and(|d| d.as_unix() > now);
+        (drafts || !is_draft) && (future || !is_future)
+    }
+
+    /// The date to report as the last modification of the page, preferring front-matter.
+    pub fn lastmod(&self, mtime: Option<Date>) -> Option<Date> {
+        self.front_matter
+            .updated
+            .or(self.front_matter.date)
+            .or(mtime)
+    }
+}
+
+#[picante::tracked]
+pub async fn page_meta<DB: Db>(db: &DB, md_file: SourceFile) -> PicanteResult<PageMeta> {
+    let _timing = crate::timings::query(db, "page_meta", md_file);
+    let src = (*md_file.path(db)?).clone();
+    let source = source_text(db, md_file).await?.unwrap_or_default();
+
+    // Errors are reported by `page_diagnostics`
+    let front_matter = FrontMatter::from_markdown(&source).unwrap_or_default();
+
+    Ok(PageMeta {
+        url: page_url(&src),
+        src,
+        front_matter,
+    })
+}
+
+/// Whether a page is part of the build. Nothing is published until [`PublishOptions`] is set, as
+/// the current time must come from an input for the answer to be cached. Pages that cannot be
+/// decoded are always left out.
+#[picante::tracked]
+pub async fn is_published<DB: Db>(db: &DB, md_file: SourceFile) -> PicanteResult<bool> {
+    let _timing = crate::timings::query(db, "is_published", md_file);
+    if source_text(db, md_file).await?.is_err() {
+        return Ok(false);
+    }
+    let Some(options) = PublishOptions::get(db)? else {
+        return Ok(false);
+    };
+    let meta = page_meta(db, md_file).await?;
+    Ok(meta.is_published(options.drafts, options.future, options.now))
+}
+
+/// Pages included in the build. Everything that lists pages (collections, feeds, sitemaps) goes
+/// through this rather than [`ContentSet`].
+#[picante::tracked]
+pub async fn published_pages<DB: Db>(db: &DB) -> PicanteResult<Vec<PageInput>> {
+    let _timing = crate::timings::query(db, "published_pages", ());
+    let mut published = Vec::new();
+    for page in ContentSet::pages(db)?.unwrap_or_default() {
+        if is_published(db, page.file).await? {
+            published.push(page);
+        }
+    }
+    Ok(published)
+}
+
+/// Pages left out of the build, which published pages must not link to.
+#[picante::tracked]
+pub async fn excluded_pages<DB: Db>(db: &DB) -> PicanteResult<Vec<SrcPath>> {
+    let _timing = crate::timings::query(db, "excluded_pages", ());
+    let mut excluded = Vec::new();
+    for page in ContentSet::pages(db)?.unwrap_or_default() {
+        if !is_published(db, page.file).await? {
+            excluded.push((*page.file.path(db)?).clone());
+        }
+    }
+    Ok(excluded)
+}
+
+/// Published pages sharing their URL with other pages, by URL, such as `blog/index.md` and
+/// `blog/_index.md`. They would be written to the same file, so they are reported instead of one
+/// silently replacing the other.
+#[picante::tracked]
+pub async fn url_conflicts<DB: Db>(db: &DB) -> PicanteResult<Vec<(String, Vec<SrcPath>)>> {
+    let _timing = crate::timings::query(db, "url_conflicts", ());
+    let mut by_url: BTreeMap<String, Vec<SrcPath>> = BTreeMap::new();
+    for page in published_pages(db).await? {
+        let meta = page_meta(db, page.file).await?;
+        by_url.entry(meta.url).or_default().push(meta.src);
+    }
+    Ok(by_url
+        .into_iter()
+        .filter(|(_, pages)| pages.len() > 1)
+        .collect())
+}
+
+/// Current time in seconds since the unix epoch.
+pub fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Renders a standalone page along with the assets it references, which are published next to it.
+#[picante::tracked]
+pub async fn render_page<DB: Db>(db: &DB, md_file: SourceFile) -> PicanteResult<Vec<OutputFile>> {
+    let _timing = crate::timings::query(db, "render_page", md_file);
+    crate::config(db)
+        .rendered_pages
+        .fetch_add(1, Ordering::Relaxed);
+    let meta = page_meta(db, md_file).await?;
+    let chonk = render_chonk(db, md_file).await?;
+    let backlinks = backlinks(db, meta.src.clone()).await?;
+
+    let html_path = output_path(&meta.url);
+    let dir = &html_path[..html_path.len() - "index.html".len()];
+    let mut outputs = vec![OutputFile::generated(
+        html_path.clone(),
+        crate::layout::page(&crate::config(db).site, &meta, &chonk.html, &backlinks),
+    )];
+    for (src, hashed_name) in chonk.hashed_assets {
+        outputs.push(OutputFile {
+            path: format!("{}{}", dir, hashed_name),
+            contents: OutputContents::Copied(src),
+        });
+    }
+    Ok(outputs)
+}
+
+/// Maps a page to its site-relative URL. `blog/post.md` becomes `/blog/post/`, and index pages
+/// (`index.md`, `_index.md`) take the URL of their directory.
+pub fn page_url(src: &SrcPath) -> String {
+    let rel = src.rel_path();
+    let rel = rel.strip_suffix(src.ext()).unwrap_or(rel);
+    let dir = match rel.rsplit_once('/') {
+        Some((dir, "index" | "_index")) => dir,
+        None if rel == "index" || rel == "_index" => "",
+        _ => rel,
+    };
+    if dir.is_empty() {
+        "/".to_string()
+    } else {
+        format!("/{}/", dir)
+    }
+}
+
+/// Maps a site-relative URL to the file it is written to in the output directory.
+pub fn output_path(url: &str) -> String {
+    let url = url.trim_start_matches('/');
+    if url.is_empty() || url.ends_with('/') {
+        format!("{}index.html", url)
+    } else {
+        url.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_published() {
+        let meta = |yaml: &str| PageMeta {
+            src: SrcPath::from_relaxed_path("post.md", "/site/content"),
+            url: "/post/".to_string(),
+            front_matter: FrontMatter::from_yaml(yaml).unwrap(),
+        };
+        let now = Date::parse("2024-06-01").unwrap().as_unix();
+
+        let page = meta("date: 2024-01-01");
+        assert!(page.is_published(false, false, now));
+        let draft = meta("draft: true");
+        assert!(!draft.is_published(false, false, now));
+        assert!(draft.is_published(true, false, now));
+        let scheduled = meta("date: 2024-06-02");
+        assert!(!scheduled.is_published(false, false, now));
+        assert!(scheduled.is_published(false, true, now));
+    }
+
+    #[test]
+    fn test_page_url() {
+        let cases = vec![
+            ("index.md", "/"),
+            ("_index.md", "/"),
+            ("about.md", "/about/"),
+            ("blog/index.md", "/blog/"),
+            ("blog/_index.md", "/blog/"),
+            ("blog/post.md", "/blog/post/"),
+        ];
+        for (input, expected) in cases {
+            let src = SrcPath::from_relaxed_path(input, "/site/content/");
+            assert_eq!(page_url(&src), expected);
+            assert_eq!(
+                output_path(expected),
+                format!("{}index.html", &expected[1..])
+            );
+        }
+    }
+}
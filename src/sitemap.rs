@@ -0,0 +1,149 @@
+//! `sitemap.xml` generation.
+//!
+//! The sitemap is a single query over the page set. It only depends on the URL and dates of each
+//! page, so editing the body of a page does not regenerate it.
+
+use crate::OutputFile;
+use crate::db::Db;
+use crate::frontmatter::Date;
+use crate::site::{page_meta, published_pages};
+use picante::PicanteResult;
+
+/// Maximum number of URLs a single sitemap file may hold, as per the sitemap protocol.
+pub const MAX_URLS_PER_SITEMAP: usize = 50_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+struct SitemapEntry {
+    loc: String,
+    lastmod: Option<Date>,
+}
+
+#[picante::tracked]
+pub async fn sitemap<DB: Db>(db: &DB) -> PicanteResult<Vec<OutputFile>> {
+    let _timing = crate::timings::query(db, "sitemap", ());
+    let site = &crate::config(db).site;
+    let pages = published_pages(db).await?;
+
+    let mut entries = Vec::with_capacity(pages.len());
+    for page in pages {
+        let meta = page_meta(db, page.file).await?;
+        if !meta.front_matter.sitemap {
+            continue;
+        }
+        let mtime = Date::from_unix(page.mtime.modified(db)?);
+        entries.push(SitemapEntry {
+            loc: site.absolute_url(&meta.url),
+            lastmod: meta.lastmod(Some(mtime)),
+        });
+    }
+    entries.sort_by(|a, b| a.loc.cmp(&b.loc));
+
+    Ok(split_sitemap(&entries, |path| site.absolute_url(path)))
+}
+
+/// Writes the entries as a single `sitemap.xml`, or as numbered sitemaps plus a `sitemap.xml`
+/// index when there are more than [`MAX_URLS_PER_SITEMAP`] of them.
+fn split_sitemap(
+    entries: &[SitemapEntry],
+    absolute_url: impl Fn(&str) -> String,
+) -> Vec<OutputFile> {
+    if entries.len() <= MAX_URLS_PER_SITEMAP {
+        return vec![OutputFile::generated("sitemap.xml", urlset(entries))];
+    }
+
+    let mut files = Vec::new();
+    let mut index = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for (i, chunk) in entries.chunks(MAX_URLS_PER_SITEMAP).enumerate() {
+        let path = format!("sitemap{}.xml", i + 1);
+        index.push_str("  <sitemap>\n    <loc>");
+        index.push_str(&escape_xml(&absolute_url(&path)));
+        index.push_str("</loc>\n");
+        if let Some(lastmod) = chunk.iter().filter_map(|e| e.lastmod).max() {
+            index.push_str(&format!("    <lastmod>{}</lastmod>\n", lastmod));
+        }
+        index.push_str("  </sitemap>\n");
+        files.push(OutputFile::generated(path, urlset(chunk)));
+    }
+    index.push_str("</sitemapindex>\n");
+    files.push(OutputFile::generated("sitemap.xml", index));
+    files
+}
+
+fn urlset(entries: &[SitemapEntry]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for entry in entries {
+        xml.push_str("  <url>\n    <loc>");
+        xml.push_str(&escape_xml(&entry.loc));
+        xml.push_str("</loc>\n");
+        if let Some(lastmod) = entry.lastmod {
+            xml.push_str(&format!("    <lastmod>{}</lastmod>\n", lastmod));
+        }
+        xml.push_str("  </url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+fn escape_xml(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OutputContents;
+
+    fn xml(file: &OutputFile) -> &str {
+        match &file.contents {
+            OutputContents::Generated(bytes) => std::str::from_utf8(bytes).unwrap(),
+            OutputContents::Copied(_) => panic!("sitemaps are generated"),
+        }
+    }
+
+    fn entries(n: usize) -> Vec<SitemapEntry> {
+        (0..n)
+            .map(|i| SitemapEntry {
+                loc: format!("https://example.com/{}/", i),
+                lastmod: Some(Date::from_unix(i as i64)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_single_sitemap() {
+        let files = split_sitemap(&entries(2), |p| format!("https://example.com/{}", p));
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, "sitemap.xml");
+        assert!(xml(&files[0]).contains("<loc>https://example.com/1/</loc>"));
+        assert!(xml(&files[0]).contains("<lastmod>1970-01-01T00:00:01Z</lastmod>"));
+    }
+
+    #[test]
+    fn test_sitemap_index() {
+        let files = split_sitemap(&entries(MAX_URLS_PER_SITEMAP + 1), |p| {
+            format!("https://example.com/{}", p)
+        });
+        let paths: Vec<_> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["sitemap1.xml", "sitemap2.xml", "sitemap.xml"]);
+        assert!(xml(&files[2]).contains("<sitemapindex"));
+        assert!(xml(&files[2]).contains("<loc>https://example.com/sitemap2.xml</loc>"));
+        assert_eq!(xml(&files[1]).matches("<url>").count(), 1);
+    }
+}
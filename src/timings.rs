@@ -0,0 +1,274 @@
+//! Where a build spends its time. Once enabled with [`enable`], every query that runs records a
+//! span, and top-level lookups the cache answered are recorded as well, so a slow rebuild shows
+//! both what was recomputed and what was not.
+//!
+//! The spans can be exported as a Chrome trace (`chrome://tracing`, Perfetto) or summarized.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::db::{AaskaDb, Db, SourceFile};
+use crate::path::SrcPath;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryTiming {
+    /// Name of the query, e.g. `render_chonk`
+    pub query: &'static str,
+    /// What the query ran on, usually a page
+    pub key: String,
+    /// Since timings were enabled
+    pub start: Duration,
+    pub end: Duration,
+    /// Answered from the cache rather than recomputed
+    pub cached: bool,
+    /// Small number identifying the thread the query started on
+    pub thread: u64,
+}
+
+impl QueryTiming {
+    pub fn duration(&self) -> Duration {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Timings {
+    enabled: AtomicBool,
+    epoch: Mutex<Option<Instant>>,
+    events: Mutex<Vec<QueryTiming>>,
+    /// Number of times each query ran, by `query key`, to tell cached lookups apart
+    executions: Mutex<HashMap<String, usize>>,
+}
+
+impl Timings {
+    fn now(&self) -> Duration {
+        self.epoch
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Instant::now)
+            .elapsed()
+    }
+
+    fn executions(&self, id: &str) -> usize {
+        self.executions
+            .lock()
+            .unwrap()
+            .get(id)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Starts recording the queries run against `db`.
+pub fn enable(db: &AaskaDb) {
+    let config = crate::config(db);
+    config.timings.now();
+    config.timings.enabled.store(true, Ordering::Relaxed);
+}
+
+/// The spans recorded so far, ordered by start time, clearing them.
+pub fn take(db: &AaskaDb) -> Vec<QueryTiming> {
+    let mut events = std::mem::take(&mut *crate::config(db).timings.events.lock().unwrap());
+    events.sort_by_key(|e| (e.start, e.thread));
+    events
+}
+
+/// What a query runs on, as shown in timings.
+pub(crate) trait TimingKey {
+    fn label<DB: Db>(&self, db: &DB) -> String;
+}
+
+impl TimingKey for () {
+    fn label<DB: Db>(&self, _db: &DB) -> String {
+        String::new()
+    }
+}
+
+impl TimingKey for SourceFile {
+    fn label<DB: Db>(&self, db: &DB) -> String {
+        self.path(db)
+            .map(|path| path.rel_path().to_string())
+            .unwrap_or_default()
+    }
+}
+
+impl TimingKey for SrcPath {
+    fn label<DB: Db>(&self, _db: &DB) -> String {
+        self.rel_path().to_string()
+    }
+}
+
+impl TimingKey for String {
+    fn label<DB: Db>(&self, _db: &DB) -> String {
+        self.clone()
+    }
+}
+
+/// Records the execution of a query until dropped. Called first thing in the body of each query,
+/// which only runs when the query is recomputed.
+pub(crate) fn query<DB: Db>(
+    db: &DB,
+    query: &'static str,
+    key: impl TimingKey,
+) -> Option<QuerySpan> {
+    let config = crate::config(db);
+    if !config.timings.enabled.load(Ordering::Relaxed) {
+        return None;
+    }
+    let key = key.label(db);
+    *config
+        .timings
+        .executions
+        .lock()
+        .unwrap()
+        .entry(format!("{} {}", query, key))
+        .or_default() += 1;
+    Some(QuerySpan {
+        start: config.timings.now(),
+        thread: thread_index(),
+        config,
+        query,
+        key,
+    })
+}
+
+pub(crate) struct QuerySpan {
+    config: Arc<crate::Config>,
+    query: &'static str,
+    key: String,
+    start: Duration,
+    thread: u64,
+}
+
+impl Drop for QuerySpan {
+    fn drop(&mut self) {
+        let timings = &self.config.timings;
+        let event = QueryTiming {
+            query: self.query,
+            key: std::mem::take(&mut self.key),
+            start: self.start,
+            end: timings.now(),
+            cached: false,
+            thread: self.thread,
+        };
+        timings.events.lock().unwrap().push(event);
+    }
+}
+
+/// Awaits a query, recording a span if the cache answered it. Recomputations are recorded by the
+/// query itself, see [`query`].
+pub(crate) async fn lookup<DB: Db, F: Future>(
+    db: &DB,
+    query: &'static str,
+    key: impl TimingKey,
+    future: F,
+) -> F::Output {
+    let config = crate::config(db);
+    if !config.timings.enabled.load(Ordering::Relaxed) {
+        return future.await;
+    }
+    let key = key.label(db);
+    let id = format!("{} {}", query, key);
+    let executions = config.timings.executions(&id);
+    let start = config.timings.now();
+    let thread = thread_index();
+    let output = future.await;
+    if config.timings.executions(&id) == executions {
+        let event = QueryTiming {
+            query,
+            key,
+            start,
+            end: config.timings.now(),
+            cached: true,
+            thread,
+        };
+        config.timings.events.lock().unwrap().push(event);
+    }
+    output
+}
+
+fn thread_index() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    thread_local! {
+        static INDEX: u64 = NEXT.fetch_add(1, Ordering::Relaxed);
+    }
+    INDEX.with(|index| *index)
+}
+
+/// The spans in the Chrome trace event format, as complete events on one track per thread.
+pub fn chrome_trace(events: &[QueryTiming]) -> String {
+    let events: Vec<_> = events
+        .iter()
+        .map(|e| {
+            serde_json::json!({
+                "name": e.query,
+                "cat": if e.cached { "cached" } else { "recomputed" },
+                "ph": "X",
+                "ts": e.start.as_micros() as u64,
+                "dur": e.duration().as_micros() as u64,
+                "pid": 1,
+                "tid": e.thread,
+                "args": { "key": e.key },
+            })
+        })
+        .collect();
+    serde_json::json!({ "traceEvents": events, "displayTimeUnit": "ms" }).to_string()
+}
+
+/// The `n` slowest spans, one per line, after a count of recomputed and cached queries.
+pub fn summary(events: &[QueryTiming], n: usize) -> String {
+    let cached = events.iter().filter(|e| e.cached).count();
+    let mut summary = format!(
+        "{} queries recomputed, {} answered from cache\n",
+        events.len() - cached,
+        cached
+    );
+    let mut slowest: Vec<_> = events.iter().collect();
+    slowest.sort_by_key(|e| std::cmp::Reverse(e.duration()));
+    for e in slowest.into_iter().take(n) {
+        summary.push_str(&format!("{:>10.2?}  {}", e.duration(), e.query));
+        if !e.key.is_empty() {
+            summary.push_str(&format!(" {}", e.key));
+        }
+        summary.push_str(if e.cached { " (cached)\n" } else { "\n" });
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let timing = |query, key: &str, start, end, cached| QueryTiming {
+            query,
+            key: key.to_string(),
+            start: Duration::from_millis(start),
+            end: Duration::from_millis(end),
+            cached,
+            thread: 0,
+        };
+        let events = vec![
+            timing("render_page", "index.md", 0, 30, false),
+            timing("render_chonk", "index.md", 1, 25, false),
+            timing("render_page", "blog/post.md", 30, 31, true),
+        ];
+
+        let summary = summary(&events, 2);
+        let lines: Vec<_> = summary.lines().collect();
+        assert_eq!(lines[0], "2 queries recomputed, 1 answered from cache");
+        assert!(lines[1].ends_with("render_page index.md"));
+        assert!(lines[2].ends_with("render_chonk index.md"));
+        assert_eq!(lines.len(), 3);
+
+        let trace: serde_json::Value = serde_json::from_str(&chrome_trace(&events)).unwrap();
+        let first = &trace["traceEvents"][0];
+        assert_eq!(first["ph"], "X");
+        assert_eq!(first["dur"], 30_000);
+        assert_eq!(trace["traceEvents"][2]["cat"], "cached");
+    }
+}
@@ -0,0 +1,269 @@
+//! Filesystem access for source files. Everything the build reads goes through a [`Vfs`], so
+//! content can come from memory (tests, embedding applications) or from unsaved editor buffers
+//! laid over the disk ([`OverlayVfs`]).
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+pub trait Vfs: Send + Sync {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    fn is_file(&self, path: &Path) -> bool;
+
+    /// Last modification time, in seconds since the unix epoch
+    fn modified(&self, path: &Path) -> io::Result<i64>;
+
+    /// Absolute path of a file with its symlinks followed. Fails if the file does not exist.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Files under `dir` whose path relative to `dir` matches the glob `pattern`, sorted.
+    fn glob(&self, dir: &Path, pattern: &str) -> io::Result<Vec<PathBuf>>;
+}
+
+/// The real filesystem.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiskVfs;
+
+impl Vfs for DiskVfs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<i64> {
+        Ok(std::fs::metadata(path)?
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default())
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        std::fs::canonicalize(path)
+    }
+
+    fn glob(&self, dir: &Path, pattern: &str) -> io::Result<Vec<PathBuf>> {
+        let full = dir.join(pattern);
+        let full = full.to_str().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Glob pattern {} is not valid UTF-8", full.display()),
+            )
+        })?;
+        let mut paths = glob::glob(full)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+            .map(|entry| entry.map_err(io::Error::from))
+            .collect::<io::Result<Vec<_>>>()?;
+        paths.retain(|path| path.is_file());
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MemoryFile {
+    contents: Vec<u8>,
+    modified: i64,
+}
+
+/// Files held in memory, keyed by absolute path.
+#[derive(Debug, Default)]
+pub struct MemoryVfs {
+    files: RwLock<BTreeMap<PathBuf, MemoryFile>>,
+}
+
+impl MemoryVfs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces a file, with a modification time of 0 so that builds are reproducible.
+    pub fn insert(&self, path: impl Into<PathBuf>, contents: impl Into<Vec<u8>>) {
+        self.insert_with_modified(path, contents, 0);
+    }
+
+    pub fn insert_with_modified(
+        &self,
+        path: impl Into<PathBuf>,
+        contents: impl Into<Vec<u8>>,
+        modified: i64,
+    ) {
+        let file = MemoryFile {
+            contents: contents.into(),
+            modified,
+        };
+        let path = crate::path::normalize_path(path.into());
+        self.files.write().unwrap().insert(path, file);
+    }
+
+    pub fn remove(&self, path: &Path) -> bool {
+        let path = crate::path::normalize_path(path);
+        self.files.write().unwrap().remove(&path).is_some()
+    }
+
+    fn get(&self, path: &Path) -> io::Result<MemoryFile> {
+        let path = crate::path::normalize_path(path);
+        self.files
+            .read()
+            .unwrap()
+            .get(&path)
+            .cloned()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} is not in memory", path.display()),
+                )
+            })
+    }
+}
+
+impl Vfs for MemoryVfs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        Ok(self.get(path)?.contents)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.get(path).is_ok()
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<i64> {
+        Ok(self.get(path)?.modified)
+    }
+
+    /// There are no symlinks in memory, a file is where its path says.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.get(path)?;
+        Ok(crate::path::normalize_path(path))
+    }
+
+    fn glob(&self, dir: &Path, pattern: &str) -> io::Result<Vec<PathBuf>> {
+        let pattern = glob::Pattern::new(pattern)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        let dir = crate::path::normalize_path(dir);
+        Ok(self
+            .files
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|path| {
+                path.strip_prefix(&dir)
+                    .is_ok_and(|rel| pattern.matches_path_with(rel, options))
+            })
+            .cloned()
+            .collect())
+    }
+}
+
+/// Files from `upper` shadow those of `lower`, as unsaved buffers shadow the files on disk.
+#[derive(Clone)]
+pub struct OverlayVfs {
+    upper: Arc<dyn Vfs>,
+    lower: Arc<dyn Vfs>,
+}
+
+impl OverlayVfs {
+    pub fn new(upper: Arc<dyn Vfs>, lower: Arc<dyn Vfs>) -> Self {
+        Self { upper, lower }
+    }
+}
+
+impl Vfs for OverlayVfs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.upper.read(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => self.lower.read(path),
+            result => result,
+        }
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.upper.is_file(path) || self.lower.is_file(path)
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<i64> {
+        match self.upper.modified(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => self.lower.modified(path),
+            result => result,
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.upper.canonicalize(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => self.lower.canonicalize(path),
+            result => result,
+        }
+    }
+
+    fn glob(&self, dir: &Path, pattern: &str) -> io::Result<Vec<PathBuf>> {
+        let mut paths = self.upper.glob(dir, pattern)?;
+        paths.extend(self.lower.glob(dir, pattern)?);
+        paths.sort();
+        paths.dedup();
+        Ok(paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_and_overlay() {
+        let lower = Arc::new(MemoryVfs::new());
+        lower.insert("/site/content/index.md", "# Home");
+        lower.insert("/site/content/blog/post.md", "# Post");
+        lower.insert("/site/content/blog/image.png", "png");
+        lower.insert("/site/other.md", "");
+
+        let dir = Path::new("/site/content");
+        assert_eq!(
+            lower.glob(dir, "**/*.md").unwrap(),
+            vec![
+                PathBuf::from("/site/content/blog/post.md"),
+                PathBuf::from("/site/content/index.md"),
+            ]
+        );
+        assert_eq!(
+            lower.glob(dir, "*.md").unwrap(),
+            vec![PathBuf::from("/site/content/index.md")]
+        );
+
+        let upper = Arc::new(MemoryVfs::new());
+        upper.insert_with_modified("/site/content/index.md", "# Unsaved", 42);
+        upper.insert("/site/content/new.md", "# New");
+        let overlay = OverlayVfs::new(upper, lower);
+        assert_eq!(
+            overlay.read(Path::new("/site/content/index.md")).unwrap(),
+            b"# Unsaved"
+        );
+        assert_eq!(
+            overlay
+                .modified(Path::new("/site/content/./index.md"))
+                .unwrap(),
+            42
+        );
+        assert_eq!(
+            overlay
+                .read(Path::new("/site/content/blog/post.md"))
+                .unwrap(),
+            b"# Post"
+        );
+        assert_eq!(overlay.glob(dir, "**/*.md").unwrap().len(), 3);
+        assert_eq!(
+            overlay
+                .canonicalize(Path::new("/site/content/blog/../new.md"))
+                .unwrap(),
+            PathBuf::from("/site/content/new.md")
+        );
+        assert!(overlay.canonicalize(Path::new("/site/missing.md")).is_err());
+        assert!(!overlay.is_file(Path::new("/site/content/missing.md")));
+    }
+}
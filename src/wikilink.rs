@@ -0,0 +1,129 @@
+//! Wikilinks (`[[Page]]`, `[[Page|label]]`, `[[Page#Heading]]`), resolved by file name, path or
+//! front-matter title across the whole content tree rather than relative to the linking page.
+
+use crate::Heading;
+use crate::db::{Db, SourceFile};
+use crate::path::SrcPath;
+use crate::site::{ContentSet, page_meta};
+use picante::PicanteResult;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum WikiTarget {
+    Found(SourceFile),
+    Ambiguous(Vec<SrcPath>),
+    Missing,
+}
+
+/// Every name a page can be linked by, paired with the page. Names are normalized with
+/// [`normalize`].
+#[picante::tracked]
+pub async fn wikilink_index<DB: Db>(db: &DB) -> PicanteResult<Vec<(String, SourceFile)>> {
+    let _timing = crate::timings::query(db, "wikilink_index", ());
+    let mut index = Vec::new();
+    for page in ContentSet::pages(db)?.unwrap_or_default() {
+        let meta = page_meta(db, page.file).await?;
+        let mut names = vec![
+            normalize(meta.src.filename_no_ext()),
+            normalize(meta.src.rel_path()),
+        ];
+        if let Some(title) = &meta.front_matter.title {
+            names.push(normalize(title));
+        }
+        names.sort();
+        names.dedup();
+        index.extend(names.into_iter().map(|name| (name, page.file)));
+    }
+    index.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(index)
+}
+
+/// Resolves the page part of a wikilink. Kept as its own query so pages linking to `name` only
+/// re-render when what it resolves to changes, not whenever any page is renamed.
+#[picante::tracked]
+pub async fn resolve_wikilink<DB: Db>(db: &DB, name: String) -> PicanteResult<WikiTarget> {
+    let _timing = crate::timings::query(db, "resolve_wikilink", name.clone());
+    let name = normalize(&name);
+    let mut matches: Vec<SourceFile> = wikilink_index(db)
+        .await?
+        .into_iter()
+        .filter(|(key, _)| *key == name)
+        .map(|(_, page)| page)
+        .collect();
+    matches.dedup();
+
+    Ok(match matches.len() {
+        0 => WikiTarget::Missing,
+        1 => WikiTarget::Found(matches[0]),
+        _ => {
+            let mut candidates = Vec::with_capacity(matches.len());
+            for file in matches {
+                candidates.push((*file.path(db)?).clone());
+            }
+            WikiTarget::Ambiguous(candidates)
+        }
+    })
+}
+
+/// Splits a wikilink destination into the page name and the heading, if any.
+pub fn split(dest: &str) -> (&str, Option<&str>) {
+    match dest.split_once('#') {
+        Some((name, heading)) => (name, Some(heading)),
+        None => (dest, None),
+    }
+}
+
+/// Finds the heading a wikilink names after its `#`, by id (`intro-1`, or one set with `{#id}`)
+/// or by text.
+pub fn find_heading<'a>(headings: &'a [Heading], fragment: &str) -> Option<&'a Heading> {
+    let fragment = fragment.trim();
+    let slug = crate::collections::slugify(fragment);
+    headings
+        .iter()
+        .find(|h| h.id == fragment)
+        .or_else(|| {
+            headings
+                .iter()
+                .find(|h| h.text.trim().to_lowercase() == fragment.to_lowercase())
+        })
+        .or_else(|| headings.iter().find(|h| h.id == slug))
+}
+
+/// Wikilinks are matched case-insensitively, with or without the `.md` extension.
+pub fn normalize(name: &str) -> String {
+    let name = name.trim();
+    let name = name.strip_suffix(".md").unwrap_or(name);
+    name.to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_normalize() {
+        assert_eq!(split("Page#Some Heading"), ("Page", Some("Some Heading")));
+        assert_eq!(split("notes/Page"), ("notes/Page", None));
+        assert_eq!(normalize(" My Page.md "), "my page");
+    }
+
+    #[test]
+    fn test_find_heading() {
+        let heading = |id: &str, text: &str| Heading {
+            level: 2,
+            id: id.to_string(),
+            text: text.to_string(),
+        };
+        let headings = vec![
+            heading("intro", "Intro"),
+            heading("intro-1", "Intro"),
+            heading("install", "Getting started"),
+        ];
+        let find = |fragment| find_heading(&headings, fragment).map(|h| h.id.as_str());
+        assert_eq!(find("Intro"), Some("intro"));
+        assert_eq!(find("intro-1"), Some("intro-1"));
+        assert_eq!(find("getting started"), Some("install"));
+        assert_eq!(find("install"), Some("install"));
+        assert_eq!(find("Getting-Started"), None);
+        assert_eq!(find("Missing"), None);
+    }
+}